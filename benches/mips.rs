@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
+use j1::block::Engine;
+use j1::cpu::CPU;
+use j1::j1e_bin;
+use j1::utils::read_forth_source;
+
+// runs of each workload, the best one is reported
+const RUNS: usize = 5;
+
+// how a workload is run
+#[derive(Clone, Copy, Debug)]
+enum Mode {
+    // `CPU::step` one instruction at a time with a step result each, the
+    // path breakpoints, a tracer, a history or a waveform run on
+    Step,
+    // `CPU::run` with an engine
+    Run(Engine),
+}
+
+// eForth workloads: resources/simple.fth from boot, then a deeper recursive
+// fib on the booted image
+fn main() -> std::io::Result<()> {
+    let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    p.push("resources/simple.fth");
+    let script = read_forth_source(&p.to_str().unwrap().to_string())?;
+    let fib = b"decimal : fib dup 2 < if drop 1 else dup 2 - recurse swap 1 - recurse + then ;\n".to_vec();
+    let workloads: [(&str, &[u8], &[u8]); 2] = [("simple.fth", &[], &script), ("25 fib", &fib, b"25 fib .\n")];
+    for (name, setup, workload) in workloads.iter() {
+        let step = bench(Mode::Step, name, setup, workload)?;
+        for engine in [Engine::Interpreter, Engine::Blocks].iter() {
+            let mips = bench(Mode::Run(*engine), name, setup, workload)?;
+            println!("    {:.2}x the step by step MIPS", mips / step);
+        }
+    }
+    Ok(())
+}
+
+// runs `setup` untimed, reports the MIPS of running `workload` after it and
+// returns the best MIPS
+fn bench(mode: Mode, name: &str, setup: &[u8], workload: &[u8]) -> std::io::Result<f64> {
+    let mut best = 0.0f64;
+    let mut instructions = 0;
+    for _ in 0..RUNS {
+        let engine = match mode {
+            Mode::Run(engine) => engine,
+            Mode::Step => Engine::Interpreter,
+        };
+        let mut cpu = CPU::builder().stop_on_idle(true).engine(engine).build()?;
+        cpu.load_bytes(&j1e_bin::J1E_BIN)?;
+        if !setup.is_empty() {
+            cpu.run(setup.to_vec())?;
+        }
+        let before = cpu.cycles();
+        let start = Instant::now();
+        match mode {
+            Mode::Run(_) => {
+                cpu.run(workload.to_vec())?;
+            }
+            Mode::Step => {
+                cpu.load_input(workload.to_vec());
+                cpu.step()?;
+                while !cpu.is_waiting_for_input() {
+                    cpu.step()?;
+                }
+            }
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        instructions = cpu.cycles() - before;
+        best = best.max(instructions as f64 / elapsed / 1e6);
+    }
+    println!("{:?} {}: {} instructions, {:.1} MIPS (best of {})", mode, name, instructions, best, RUNS);
+    Ok(best)
+}
@@ -2,10 +2,19 @@ use std::io::{Error, ErrorKind};
 extern crate clap;
 use std::ffi::OsString;
 use clap::{App, Arg};
-use j1::cpu::CPU;
+use j1::block::Engine;
+use j1::board::Board;
+use j1::cpu::{Limits, StopReason, CPU};
 use j1::j1e_bin;
+use j1::snapshot::Snapshot;
+use j1::trace::{TraceFormat, Tracer};
 use j1::utils::{read_binary, read_forth_source, write_log_file};
+use j1::vcd::{Waveform, WaveformFormat};
+use j1::verilog::Timing;
+use std::fs::File;
 use std::io;
+use std::io::BufWriter;
+use std::time::Duration;
 
 #[allow(unused_assignments)]
 fn main() -> std::io::Result<()> {
@@ -19,13 +28,13 @@ fn main() -> std::io::Result<()> {
 
     // read a forth script file
     let mut script: Vec<u8> = Vec::new();
-    if args.script_file_name.len() > 0 {
+    if !args.script_file_name.is_empty() {
         script = read_forth_source(&args.script_file_name)?;
     }
 
     // read a j1 binary file
     let mut binary: Vec<u8> = Vec::new();
-    if args.bin_file_name.len() == 0 {
+    if args.bin_file_name.is_empty() {
         println!("loaded j1e binary");
         binary = j1e_bin::J1E_BIN.to_vec();
     } else {
@@ -33,59 +42,134 @@ fn main() -> std::io::Result<()> {
         binary = read_binary(&args.bin_file_name)?;
     }
 
-    // make a j1 cpu and run the script
+    // make a j1 cpu and run the script, a mapped file needs the 16K words of j1.c
     let mut done = false;
-    let mut cpu = CPU::new();
+    let mut memory_size = args.memory_size;
+    if !args.map_file_name.is_empty() {
+        memory_size = memory_size.max(0x4000);
+    }
+    let mut cpu = CPU::builder().board(args.board).memory_size(memory_size).engine(args.engine).timing(args.timing).build()?;
     cpu.load_bytes(&binary)?;
-    cpu.run(script).or_else(|e| {
-        if e == "bye" {
-            done = true;
-            Ok(())
+    if !args.map_file_name.is_empty() {
+        cpu.map_file(&read_binary(&args.map_file_name)?)?;
+        println!("mapped file: {}", args.map_file_name);
+    }
+    if !args.restore_file_name.is_empty() {
+        cpu.restore(&Snapshot::load(&args.restore_file_name)?)?;
+        println!("restored snapshot: {}", args.restore_file_name);
+    }
+    cpu.limits = args.limits;
+    cpu.stop_on_idle = true;
+    if !args.trace_file_name.is_empty() {
+        // stream the trace to the file unless only the last instructions are kept
+        cpu.tracer = Some(if args.trace_last > 0 {
+            Tracer::ring(args.trace_last)
         } else {
-            Err(Error::new(ErrorKind::Other, e))
-        }
-    })?;
+            Tracer::stream(BufWriter::new(File::create(&args.trace_file_name)?), args.trace_format)?
+        });
+    }
+    if !args.waveform_file_name.is_empty() {
+        cpu.waveform = Some(Waveform::new());
+    }
+    match cpu.run(script) {
+        Ok(StopReason::Bye) => done = true,
+        Ok(_) => (),
+        Err(e) => fault(&args, &cpu, e),
+    }
+    if !args.snapshot_file_name.is_empty() {
+        cpu.snapshot().save(&args.snapshot_file_name)?;
+        println!("snapshot written to: {}", &args.snapshot_file_name);
+    }
 
     if args.repl {
         print!("{}", cpu.console.get_log());
         cpu.console.writer.clear();
         while !done {
             let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
-            if input.len() > 0 {
-                cpu.run(Vec::from(input)).or_else(|e| {
-                    if e == "bye" {
-                        done = true;
-                        Ok(())
-                    } else {
-                        Err(Error::new(ErrorKind::Other, e))
-                    }
-                })?;
+            if io::stdin().read_line(&mut input)? == 0 {
+                break;
+            }
+            if !input.is_empty() {
+                match cpu.run(Vec::from(input)) {
+                    Ok(StopReason::Bye) => done = true,
+                    Ok(_) => (),
+                    Err(e) => fault(&args, &cpu, e),
+                }
                 print!("{}", cpu.console.get_writer());
                 cpu.console.writer.clear();
             }
         }
+        write_trace(&args, &cpu)?;
     } else {
-        let log_file_name = args.script_file_name.clone() + "-log.txt";
-        write_log_file(&log_file_name, &cpu.console.get_log())?;
-        println!("log written to: {}", &log_file_name);
+        write_trace(&args, &cpu)?;
+        write_log(&args, &cpu)?;
         // println!("{}", cpu.console.get_log());
         println!("\nExiting j1...");
     }
     Ok(())
 }
 
+// reports a cpu fault, saves the log and trace and exits with the fault's exit code
+fn fault(args: &Args, cpu: &CPU, e: j1::error::Error) -> ! {
+    if args.repl {
+        print!("{}", cpu.console.get_writer());
+    } else {
+        let _ = write_log(args, cpu);
+    }
+    let _ = write_trace(args, cpu);
+    eprintln!("\nj1 fault: {}", e);
+    std::process::exit(e.exit_code())
+}
+
+fn write_log(args: &Args, cpu: &CPU) -> std::io::Result<()> {
+    let log_file_name = args.script_file_name.clone() + "-log.txt";
+    write_log_file(&log_file_name, &cpu.console.get_log())?;
+    println!("log written to: {}", &log_file_name);
+    Ok(())
+}
+
+fn write_trace(args: &Args, cpu: &CPU) -> std::io::Result<()> {
+    if let Some(tracer) = &cpu.tracer {
+        if tracer.is_stream() {
+            tracer.flush()?;
+        } else {
+            let mut w = BufWriter::new(File::create(&args.trace_file_name)?);
+            tracer.write(&mut w, args.trace_format)?;
+        }
+        println!("trace written to: {}", &args.trace_file_name);
+    }
+    if let Some(waveform) = &cpu.waveform {
+        let mut w = BufWriter::new(File::create(&args.waveform_file_name)?);
+        waveform.write(&mut w, args.waveform_format)?;
+        println!("waveform written to: {}", &args.waveform_file_name);
+    }
+    Ok(())
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Args {
     pub bin_file_name: String,
     pub script_file_name: String,
     pub repl: bool,
+    pub trace_file_name: String,
+    pub trace_format: TraceFormat,
+    pub trace_last: usize,
+    pub waveform_file_name: String,
+    pub waveform_format: WaveformFormat,
+    pub limits: Limits,
+    pub snapshot_file_name: String,
+    pub restore_file_name: String,
+    pub memory_size: usize,
+    pub map_file_name: String,
+    pub engine: Engine,
+    pub timing: Timing,
+    pub board: Board,
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::new_without_default)]
 impl Args {
     pub fn new() -> Self {
-        Self::new_from(std::env::args_os().into_iter()).unwrap_or_else(|e| e.exit())
+        Self::new_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
     }
 
     fn new_from<I, T>(args: I) -> Result<Self, clap::Error>
@@ -122,7 +206,140 @@ impl Args {
             .help("Run the J1 forth repl")
             .required(false);
 
-        let app = app.arg(bin_file_name_option).arg(script_file_name_option).arg(repl_flag);
+        // define the instruction trace file name command line option
+        let trace_file_name_option = Arg::with_name("trace_file")
+            .long("trace") // allow --trace
+            .short("t") // allow -t
+            .takes_value(true)
+            .help("Write an instruction trace to this file")
+            .required(false);
+
+        // define the trace format command line option
+        let trace_format_option = Arg::with_name("trace_format")
+            .long("trace-format") // allow --trace-format
+            .takes_value(true)
+            .possible_values(&["text", "json", "bin"])
+            .default_value("text")
+            .help("Instruction trace format")
+            .required(false);
+
+        // define the trace ring buffer size command line option
+        let trace_last_option = Arg::with_name("trace_last")
+            .long("trace-last") // allow --trace-last
+            .takes_value(true)
+            .help("Only keep the last N instructions in the trace")
+            .required(false);
+
+        // define the waveform file name command line option
+        let waveform_file_name_option = Arg::with_name("waveform_file")
+            .long("waveform") // allow --waveform
+            .takes_value(true)
+            .help("Write the j1.v signals of every clock to this file")
+            .required(false);
+
+        // define the waveform format command line option
+        let waveform_format_option = Arg::with_name("waveform_format")
+            .long("waveform-format") // allow --waveform-format
+            .takes_value(true)
+            .possible_values(&["vcd", "vectors"])
+            .default_value("vcd")
+            .help("Waveform format, vectors is one line of hex values per clock for a testbench")
+            .required(false);
+
+        // define the total instruction budget command line option
+        let max_instructions_option = Arg::with_name("max_instructions")
+            .long("max-instructions") // allow --max-instructions
+            .takes_value(true)
+            .help("Fail after executing N instructions in total")
+            .required(false);
+
+        // define the per call instruction budget command line option
+        let per_call_option = Arg::with_name("per_call")
+            .long("per-call") // allow --per-call
+            .takes_value(true)
+            .help("Fail after executing N instructions for one script or repl line")
+            .required(false);
+
+        // define the wall-clock budget command line option
+        let timeout_option = Arg::with_name("timeout")
+            .long("timeout") // allow --timeout
+            .takes_value(true)
+            .help("Fail after running SECONDS for one script or repl line")
+            .required(false);
+
+        // define the snapshot file name command line option
+        let snapshot_file_name_option = Arg::with_name("snapshot_file")
+            .long("snapshot") // allow --snapshot
+            .takes_value(true)
+            .help("Save a cpu snapshot to this file after running the script")
+            .required(false);
+
+        // define the restore file name command line option
+        let restore_file_name_option = Arg::with_name("restore_file")
+            .long("restore") // allow --restore
+            .takes_value(true)
+            .help("Start from a cpu snapshot instead of a fresh binary image")
+            .required(false);
+
+        // define the memory size command line option
+        let memory_size_option = Arg::with_name("memory_size")
+            .long("memory-size") // allow --memory-size
+            .takes_value(true)
+            .help("Memory size in 16 bit words, 0x2000 to 0x8000, default from the board")
+            .required(false);
+
+        // define the mapped file name command line option
+        let map_file_name_option = Arg::with_name("map_file")
+            .long("map-file") // allow --map-file
+            .takes_value(true)
+            .help("File to map at 0x4000 like j1.c, eForth evaluates it at startup")
+            .required(false);
+
+        // define the execution engine command line option
+        let engine_option = Arg::with_name("engine")
+            .long("engine") // allow --engine
+            .takes_value(true)
+            .possible_values(&["interpreter", "blocks"])
+            .default_value("interpreter")
+            .help("Execution engine, blocks runs cached basic blocks")
+            .required(false);
+
+        // define the timing model command line option
+        let timing_option = Arg::with_name("timing")
+            .long("timing") // allow --timing
+            .takes_value(true)
+            .possible_values(&["functional", "verilog"])
+            .help("Timing model, verilog models the RAM read latency and i/o decoding of j1.v, default from the board")
+            .required(false);
+
+        // define the board profile command line option
+        let board_option = Arg::with_name("board")
+            .long("board") // allow --board
+            .takes_value(true)
+            .possible_values(&["j1c-sim", "papilio-pro"])
+            .default_value("j1c-sim")
+            .help("Board profile, the memory size, timing and i/o map an image is built for")
+            .required(false);
+
+        let app = app
+            .arg(bin_file_name_option)
+            .arg(script_file_name_option)
+            .arg(repl_flag)
+            .arg(trace_file_name_option)
+            .arg(trace_format_option)
+            .arg(trace_last_option)
+            .arg(waveform_file_name_option)
+            .arg(waveform_format_option)
+            .arg(max_instructions_option)
+            .arg(per_call_option)
+            .arg(timeout_option)
+            .arg(snapshot_file_name_option)
+            .arg(restore_file_name_option)
+            .arg(memory_size_option)
+            .arg(map_file_name_option)
+            .arg(engine_option)
+            .arg(timing_option)
+            .arg(board_option);
         let matches = app.get_matches_from_safe(args)?;
         let bin_file_name = matches.value_of("bin_file").unwrap_or("").to_string();
         let script_file_name = matches.value_of("script_file").unwrap_or("").to_string();
@@ -130,6 +347,48 @@ impl Args {
         if matches.occurrences_of("repl") > 0 {
             repl = true
         }
-        Ok(Args { bin_file_name, script_file_name, repl })
+        let trace_file_name = matches.value_of("trace_file").unwrap_or("").to_string();
+        let trace_format = TraceFormat::from_name(matches.value_of("trace_format").unwrap()).unwrap();
+        let trace_last = matches.value_of("trace_last").unwrap_or("0").parse::<usize>()
+            .map_err(|_e| clap::Error::with_description("Invalid trace size", clap::ErrorKind::InvalidValue))?;
+        let waveform_file_name = matches.value_of("waveform_file").unwrap_or("").to_string();
+        let waveform_format = WaveformFormat::from_name(matches.value_of("waveform_format").unwrap()).unwrap();
+        let budget = |name: &str| -> Result<Option<u64>, clap::Error> {
+            matches.value_of(name).map(|s| s.parse::<u64>()).transpose()
+                .map_err(|_e| clap::Error::with_description("Invalid budget", clap::ErrorKind::InvalidValue))
+        };
+        let limits = Limits {
+            max_instructions: budget("max_instructions")?,
+            per_call: budget("per_call")?,
+            max_time: match matches.value_of("timeout") {
+                None => None,
+                Some(s) => s.parse::<f64>().ok().and_then(|x| Duration::try_from_secs_f64(x).ok())
+                    .map(Some)
+                    .ok_or_else(|| clap::Error::with_description("Invalid timeout", clap::ErrorKind::InvalidValue))?,
+            },
+        };
+        let snapshot_file_name = matches.value_of("snapshot_file").unwrap_or("").to_string();
+        let restore_file_name = matches.value_of("restore_file").unwrap_or("").to_string();
+        let board = Board::from_name(matches.value_of("board").unwrap()).unwrap();
+        let memory_size = match matches.value_of("memory_size") {
+            None => board.memory_size(),
+            Some(raw) => usize::from_str_radix(raw.trim_start_matches("0x"), 16)
+                .map_err(|_e| clap::Error::with_description("Invalid memory size", clap::ErrorKind::InvalidValue))?,
+        };
+        let map_file_name = matches.value_of("map_file").unwrap_or("").to_string();
+        let engine = match matches.value_of("engine").unwrap() {
+            "blocks" => Engine::Blocks,
+            _ => Engine::Interpreter,
+        };
+        let timing = match matches.value_of("timing") {
+            Some("verilog") => Timing::Verilog,
+            Some(_) => Timing::Functional,
+            None => board.timing(),
+        };
+        Ok(Args {
+            bin_file_name, script_file_name, repl, trace_file_name, trace_format, trace_last,
+            waveform_file_name, waveform_format, limits,
+            snapshot_file_name, restore_file_name, memory_size, map_file_name, engine, timing, board,
+        })
     }
 }
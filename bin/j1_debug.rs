@@ -0,0 +1,357 @@
+extern crate clap;
+
+use std::ffi::OsString;
+use std::io::{self, BufRead, Write};
+
+use clap::{App, Arg};
+
+use j1::breakpoint::Breakpoint;
+use j1::cpu::{StopReason, CPU};
+use j1::error::Error;
+use j1::history::History;
+use j1::instruction::{decode, Instruction};
+use j1::j1e_bin;
+use j1::utils::{read_binary, read_forth_source};
+
+const HELP: &str = "\
+commands (addresses are byte addresses in hex, as shown by j1_dump):
+  s, step [n]            execute n instructions (default 1)
+  n, next                step, running over a CALL
+  f, finish              run until the current word returns
+  c, continue            run until a breakpoint or forth waits for console input
+  rs, rstep [n]          step back n instructions (default 1)
+  rw, rwatch <addr>      run back to the last write of the cell at addr
+  goto <cycle>           move to an earlier or later cycle
+  b, break <addr>        break when the program counter reaches addr
+  w, watch <r|w|io> <addr>  break on memory read, memory write or i/o access
+  d, delete <id>         delete a breakpoint
+  i, info                list breakpoints
+  r, regs                show registers
+  st, stacks             show data and return stacks
+  l, list [addr] [n]     disassemble n instructions around addr (default pc)
+  x <addr> [n]           examine n memory cells
+  in, input <text>       queue a line of console input
+  h, help                show this help
+  q, quit                exit the debugger";
+
+// checkpoints for reverse execution, about 16 KB each
+const HISTORY_INTERVAL: u64 = 10_000;
+const HISTORY_CHECKPOINTS: usize = 1000;
+
+fn main() -> std::io::Result<()> {
+    let args = Args::new();
+    println!("Starting j1_debug...\n");
+
+    // read a j1 binary file
+    let binary = if args.bin_file_name.is_empty() {
+        println!("loaded j1e binary");
+        j1e_bin::J1E_BIN.to_vec()
+    } else {
+        println!("loaded binary: {}", args.bin_file_name);
+        read_binary(&args.bin_file_name)?
+    };
+
+    let mut cpu = CPU::new();
+    cpu.load_bytes(&binary)?;
+    cpu.stop_on_idle = true;
+    cpu.history = Some(History::bounded(HISTORY_INTERVAL, HISTORY_CHECKPOINTS));
+
+    // queue a forth script file as console input
+    if !args.script_file_name.is_empty() {
+        let script = read_forth_source(&args.script_file_name)?;
+        println!("queued script: {}", args.script_file_name);
+        cpu.load_input(script);
+    }
+    println!("type `help` for a list of commands\n");
+    println!("{}", list(&cpu, cpu.state().pc << 1, 1));
+
+    let stdin = io::stdin();
+    loop {
+        print!("(j1) ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        if words[0] == "q" || words[0] == "quit" {
+            break;
+        }
+        match command(&mut cpu, &words, line.trim_start()) {
+            Ok(s) => {
+                print_console(&mut cpu);
+                if !s.is_empty() {
+                    println!("{}", s);
+                }
+            }
+            Err(e) => println!("error: {}", e),
+        }
+    }
+    println!("\nExiting j1_debug...");
+    Ok(())
+}
+
+fn command(cpu: &mut CPU, words: &[&str], line: &str) -> Result<String, String> {
+    let arg = |i: usize| words.get(i).copied();
+    match words[0] {
+        "s" | "step" => {
+            let n = arg(1).map(parse_count).unwrap_or(Ok(1))?;
+            let mut xs = Vec::new();
+            for _ in 0..n {
+                let step = cpu.step().map_err(|e| e.to_string())?;
+                xs.push(list(cpu, step.pc << 1, 1));
+            }
+            xs.push(regs(cpu));
+            Ok(xs.join("\n"))
+        }
+        "n" | "next" => {
+            let state = cpu.state();
+            let reason = match decode(cpu.memory()[state.pc as usize]).map_err(|e| e.to_string())? {
+                Instruction::Call(_) => run_until(cpu, |c| {
+                    let s = c.state();
+                    s.pc == state.pc + 1 && s.return_depth == state.return_depth
+                })?,
+                _ => {
+                    cpu.step().map_err(|e| e.to_string())?;
+                    "stepped".to_string()
+                }
+            };
+            Ok(format!("{}\n{}\n{}", reason, list(cpu, cpu.state().pc << 1, 1), regs(cpu)))
+        }
+        "f" | "finish" => {
+            let depth = cpu.state().return_depth;
+            if depth == 0 {
+                return Err("return stack is empty".to_string());
+            }
+            let reason = run_until(cpu, |c| c.state().return_depth < depth)?;
+            Ok(format!("{}\n{}\n{}", reason, list(cpu, cpu.state().pc << 1, 1), regs(cpu)))
+        }
+        "c" | "continue" => {
+            let reason = show_stop(&cpu.resume().map_err(|e| e.to_string())?);
+            Ok(format!("{}\n{}\n{}", reason, list(cpu, cpu.state().pc << 1, 1), regs(cpu)))
+        }
+        "rs" | "rstep" => {
+            let n = arg(1).map(parse_count).unwrap_or(Ok(1))?;
+            cpu.step_back(n as u64).map_err(|e| e.to_string())?;
+            Ok(time_travelled(cpu, "stepped back".to_string()))
+        }
+        "rw" | "rwatch" => {
+            let addr = parse_addr(arg(1))?;
+            let reason = match cpu.reverse_to_write(addr).map_err(|e| e.to_string())? {
+                Some(cycle) => format!("last write of 0x{:04X} at cycle {}", addr & !1, cycle),
+                None => format!("no recorded write of 0x{:04X}", addr & !1),
+            };
+            Ok(time_travelled(cpu, reason))
+        }
+        "goto" => {
+            let cycle = arg(1).map(|s| s.parse::<u64>().map_err(|_e| format!("invalid cycle `{}`", s)))
+                .unwrap_or_else(|| Err("usage: goto <cycle>".to_string()))?;
+            cpu.goto_cycle(cycle).map_err(|e| e.to_string())?;
+            Ok(time_travelled(cpu, format!("at cycle {}", cycle)))
+        }
+        "b" | "break" => {
+            let addr = parse_addr(arg(1))?;
+            let id = cpu.breakpoints.add(Breakpoint::Pc(addr >> 1));
+            Ok(format!("breakpoint {} at 0x{:04X}", id, addr & !1))
+        }
+        "w" | "watch" => {
+            let addr = parse_addr(arg(2))?;
+            let bp = match arg(1) {
+                Some("r") => Breakpoint::Read(addr),
+                Some("w") => Breakpoint::Write(addr),
+                Some("io") => Breakpoint::Io(addr),
+                _ => return Err("usage: watch <r|w|io> <addr>".to_string()),
+            };
+            let id = cpu.breakpoints.add(bp);
+            Ok(format!("watchpoint {}: {:?}", id, bp))
+        }
+        "d" | "delete" => {
+            let id = arg(1).map(parse_count).unwrap_or_else(|| Err("usage: delete <id>".to_string()))?;
+            if cpu.breakpoints.remove(id) {
+                Ok(format!("deleted {}", id))
+            } else {
+                Err(format!("no breakpoint {}", id))
+            }
+        }
+        "i" | "info" => {
+            let xs: Vec<String> = cpu.breakpoints.list().iter()
+                .map(|(id, bp, enabled)| format!("{:>3} {:<5} {}", id, enabled, show_breakpoint(bp)))
+                .collect();
+            Ok(if xs.is_empty() { "no breakpoints".to_string() } else { xs.join("\n") })
+        }
+        "r" | "regs" => Ok(regs(cpu)),
+        "st" | "stacks" => {
+            let state = cpu.state();
+            let show = |xs: &[u16]| xs.iter().map(|x| format!("{:04X}", x)).collect::<Vec<String>>().join(" ");
+            Ok(format!("d: {} {:04X}<tos\nr: {}", show(&state.data_stack), state.t, show(&state.return_stack)))
+        }
+        "l" | "list" => {
+            let pc = cpu.state().pc << 1;
+            let addr = match arg(1) {
+                Some(_) => parse_addr(arg(1))?,
+                None => pc.saturating_sub(8),
+            };
+            let n = arg(2).map(parse_count).unwrap_or(Ok(9))?;
+            Ok(list(cpu, addr, n))
+        }
+        "x" => {
+            let addr = parse_addr(arg(1))? & !1;
+            let n = arg(2).map(parse_count).unwrap_or(Ok(8))?;
+            let memory = cpu.memory();
+            let xs: Vec<String> = (0..n)
+                .map(|i| (addr as usize >> 1) + i)
+                .take_while(|i| *i < memory.len())
+                .map(|i| format!("0x{:04X},0x{:04X}", i << 1, memory[i]))
+                .collect();
+            Ok(xs.join("\n"))
+        }
+        "in" | "input" => {
+            let text = line.split_once(char::is_whitespace).map(|(_, s)| s).unwrap_or("").trim_end();
+            let mut xs = text.as_bytes().to_vec();
+            xs.push(b'\n');
+            cpu.append_input(xs);
+            Ok(format!("queued {} bytes", text.len() + 1))
+        }
+        "h" | "help" => Ok(HELP.to_string()),
+        _ => Err(format!("unknown command `{}`, type `help`", words[0])),
+    }
+}
+
+// steps until `done` is true, a breakpoint is hit or the cpu waits for console input
+fn run_until<F: Fn(&CPU) -> bool>(cpu: &mut CPU, done: F) -> Result<String, String> {
+    let mut step_over = true;
+    loop {
+        match cpu.step_break(step_over) {
+            Ok(_) if cpu.stop_on_idle && cpu.is_waiting_for_input() => return Ok(show_stop(&StopReason::WaitingForInput)),
+            Ok(_) => (),
+            Err(Error::Breakpoint(hit)) => return Ok(show_stop(&StopReason::Breakpoint(hit))),
+            Err(Error::Bye) => return Ok(show_stop(&StopReason::Bye)),
+            Err(e) => return Err(e.to_string()),
+        }
+        step_over = false;
+        if done(cpu) {
+            return Ok("done".to_string());
+        }
+        if !cpu.stop_on_idle && !cpu.console.has_input() {
+            return Ok(show_stop(&StopReason::InputConsumed));
+        }
+    }
+}
+
+fn print_console(cpu: &mut CPU) {
+    let s = cpu.console.get_writer();
+    if !s.is_empty() {
+        print!("{}", s);
+        if !s.ends_with('\n') {
+            println!();
+        }
+        cpu.console.writer.clear();
+    }
+}
+
+fn show_stop(reason: &StopReason) -> String {
+    match reason {
+        StopReason::InputConsumed => "stopped: console input consumed".to_string(),
+        StopReason::WaitingForInput => "stopped: waiting for console input".to_string(),
+        StopReason::Bye => "stopped: bye".to_string(),
+        StopReason::Breakpoint(hit) => match hit.access {
+            Some(access) => format!("stopped: {} {} at 0x{:04X}, {:?}", hit.id, show_breakpoint(&hit.breakpoint), hit.pc << 1, access),
+            None => format!("stopped: {} {}", hit.id, show_breakpoint(&hit.breakpoint)),
+        },
+    }
+}
+
+fn show_breakpoint(bp: &Breakpoint) -> String {
+    match bp {
+        Breakpoint::Pc(pc) => format!("break 0x{:04X}", pc << 1),
+        Breakpoint::Read(addr) => format!("watch r 0x{:04X}", addr),
+        Breakpoint::Write(addr) => format!("watch w 0x{:04X}", addr),
+        Breakpoint::Io(addr) => format!("watch io 0x{:04X}", addr),
+        Breakpoint::Instruction(pattern) => format!("instruction {:?}", pattern),
+    }
+}
+
+fn regs(cpu: &CPU) -> String {
+    let s = cpu.state();
+    format!("pc 0x{:04X}  T 0x{:04X}  dsp {}  rsp {}  cycle {}", s.pc << 1, s.t, s.data_depth, s.return_depth, cpu.cycles())
+}
+
+// console output rebuilt by replaying was already printed
+fn time_travelled(cpu: &mut CPU, reason: String) -> String {
+    cpu.console.writer.clear();
+    format!("{}\n{}\n{}", reason, list(cpu, cpu.state().pc << 1, 1), regs(cpu))
+}
+
+// dump_asm lines starting at byte address `addr`, the line at pc marked with `=>`
+fn list(cpu: &CPU, addr: u16, n: usize) -> String {
+    let last = (cpu.memory().len() as u16 - 1) << 1;
+    let start = (addr & !1).min(last);
+    let end = start.saturating_add(((n.max(1) - 1) << 1) as u16).min(last);
+    let pc = cpu.state().pc << 1;
+    cpu.dump_asm(start, end).unwrap_or_default().iter()
+        .skip(1)
+        .zip((start..=end).step_by(2))
+        .map(|(x, a)| format!("{} {}", if a == pc { "=>" } else { "  " }, x))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn parse_addr(s: Option<&str>) -> Result<u16, String> {
+    let s = s.ok_or_else(|| "missing address".to_string())?;
+    u16::from_str_radix(s.trim_start_matches("0x").trim_start_matches("0X"), 16)
+        .map_err(|_e| format!("invalid address `{}`", s))
+}
+
+fn parse_count(s: &str) -> Result<usize, String> {
+    s.parse::<usize>().map_err(|_e| format!("invalid number `{}`", s))
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub bin_file_name: String,
+    pub script_file_name: String,
+}
+
+#[allow(dead_code, clippy::new_without_default)]
+impl Args {
+    pub fn new() -> Self {
+        Self::new_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    fn new_from<I, T>(args: I) -> Result<Self, clap::Error>
+        where
+            I: Iterator<Item=T>,
+            T: Into<OsString> + Clone,
+    {
+        // basic app information
+        let app = App::new("j1_debug")
+            .version("1.0")
+            .about("J1 cpu debugger")
+            .author("Roy Crippen");
+
+        // define the binary file name command line option
+        let bin_file_name_option = Arg::with_name("bin_file")
+            .long("bin") // allow --bin
+            .short("b") // allow -b
+            .takes_value(true)
+            .help("Binary J1 forth image to load, default is j1e.bin")
+            .required(false);
+
+        // define the forth script file name command line option
+        let script_file_name_option = Arg::with_name("script_file")
+            .long("script") // allow --script
+            .short("s") // allow -s
+            .takes_value(true)
+            .help("Forth script file to queue as console input")
+            .required(false);
+
+        let app = app.arg(bin_file_name_option).arg(script_file_name_option);
+        let matches = app.get_matches_from_safe(args)?;
+        let bin_file_name = matches.value_of("bin_file").unwrap_or("").to_string();
+        let script_file_name = matches.value_of("script_file").unwrap_or("").to_string();
+        Ok(Args { bin_file_name, script_file_name })
+    }
+}
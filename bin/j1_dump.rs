@@ -1,11 +1,11 @@
 extern crate clap;
 
 use std::ffi::OsString;
-use std::io::{Error, ErrorKind};
 
 use clap::{App, Arg};
 
 use j1::cpu::CPU;
+use j1::instruction::Notation;
 use j1::j1e_bin;
 use j1::utils::{read_binary, read_forth_source};
 
@@ -16,36 +16,33 @@ fn main() -> std::io::Result<()> {
 
     // read a forth script file
     let mut script: Vec<u8> = Vec::new();
-    if args.script_file_name.len() > 0 {
+    if !args.script_file_name.is_empty() {
         script = read_forth_source(&args.script_file_name)?;
     }
 
     // read a j1 binary file
     let mut binary: Vec<u8> = Vec::new();
-    if args.bin_file_name.len() == 0 {
+    if args.bin_file_name.is_empty() {
         binary = j1e_bin::J1E_BIN.to_vec();
     } else {
         binary = read_binary(&args.bin_file_name)?;
     }
 
-    // make a j1 cpu and run the script
-    let mut done = false;
-    let mut cpu = CPU::new();
+    // make a j1 cpu and run the script, a bye still dumps memory
+    let mut cpu = CPU::builder().memory_size(args.memory_size).build()?;
     cpu.load_bytes(&binary)?;
-    cpu.run(script).or_else(|e| {
-        if e == "bye" {
-            done = true;
-            Ok(())
-        } else {
-            Err(Error::new(ErrorKind::Other, e))
-        }
-    })?;
+    if let Err(e) = cpu.run(script) {
+        eprintln!("j1_dump fault: {}", e);
+        std::process::exit(e.exit_code());
+    }
 
     let mut xs = Vec::new();
     if args.ast {
-        xs = cpu.dump_ast(args.addr_start, args.addr_end);
+        xs = cpu.dump_ast(args.addr_start, args.addr_end)?;
+    } else if args.symbols {
+        xs = cpu.dump_symbols(args.addr_start, args.addr_end, args.notation)?;
     } else {
-        xs = cpu.dump_asm(args.addr_start, args.addr_end);
+        xs = cpu.dump_asm_as(args.addr_start, args.addr_end, args.notation)?;
     }
     xs.iter().for_each(|x| println!("{}", x));
 
@@ -58,13 +55,16 @@ pub struct Args {
     pub script_file_name: String,
     pub addr_start: u16,
     pub addr_end: u16,
-    pub ast: bool
+    pub ast: bool,
+    pub symbols: bool,
+    pub notation: Notation,
+    pub memory_size: usize,
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::new_without_default)]
 impl Args {
     pub fn new() -> Self {
-        Self::new_from(std::env::args_os().into_iter()).unwrap_or_else(|e| e.exit())
+        Self::new_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
     }
 
     fn new_from<I, T>(args: I) -> Result<Self, clap::Error>
@@ -118,12 +118,39 @@ impl Args {
             .help("Dump Abstract Syntax Tree of instructions (instead of assembly")
             .required(false);
 
+        // define symbols command line flag
+        let symbols_flag = Arg::with_name("symbols")
+            .long("symbols") // allow --symbols
+            .conflicts_with("ast")
+            .help("Dump assembly with eForth word labels, call and branch target comments, and headers, strings and data as directives")
+            .required(false);
+
+        // define the instruction notation command line option
+        let notation_option = Arg::with_name("notation")
+            .long("notation") // allow --notation
+            .takes_value(true)
+            .possible_values(&["fields", "forth", "j1asm"])
+            .default_value("fields")
+            .help("Instruction notation, forth names ALU instructions after the j1.4th words they compile from, j1asm is j1.4th assembler source")
+            .required(false);
+
+        // define the memory size command line option
+        let memory_size_option = Arg::with_name("memory_size")
+            .long("memory-size") // allow --memory-size
+            .takes_value(true)
+            .default_value("0x2000")
+            .help("Memory size in 16 bit words, 0x2000 to 0x8000")
+            .required(false);
+
         let app = app
             .arg(bin_file_name_option)
             .arg(script_file_name_option)
             .arg(addr_start_option)
             .arg(addr_end_option)
-            .arg(ast_flag);
+            .arg(ast_flag)
+            .arg(symbols_flag)
+            .arg(notation_option)
+            .arg(memory_size_option);
 
         let matches = app.get_matches_from_safe(args)?;
         let bin_file_name = matches.value_of("bin_file").unwrap_or("").to_string();
@@ -132,13 +159,18 @@ impl Args {
         let err = clap::Error::with_description("Invalid address end", clap::ErrorKind::InvalidValue);
         let raw = matches.value_of("addr_start").unwrap();
         let without_prefix = raw.trim_start_matches("0x");
-        let addr_start = u16::from_str_radix(without_prefix, 16).or_else(|_e| Err(&err)).unwrap();
+        let addr_start = u16::from_str_radix(without_prefix, 16).map_err(|_e| &err).unwrap();
 
         let raw = matches.value_of("addr_end").unwrap();
         let without_prefix = raw.trim_start_matches("0x");
-        let addr_end = u16::from_str_radix(without_prefix, 16).or_else(|_e| Err(&err)).unwrap();
+        let addr_end = u16::from_str_radix(without_prefix, 16).map_err(|_e| &err).unwrap();
+
+        let raw = matches.value_of("memory_size").unwrap();
+        let memory_size = usize::from_str_radix(raw.trim_start_matches("0x"), 16)
+            .map_err(|_e| clap::Error::with_description("Invalid memory size", clap::ErrorKind::InvalidValue))?;
 
-        if addr_start > addr_end || addr_end > j1::cpu::MEMORY_SIZE as u16 {
+        // byte addresses of cells inside memory
+        if addr_start > addr_end || (addr_end >> 1) as usize >= memory_size {
             return Err(clap::Error::with_description("Invalid addresses", clap::ErrorKind::InvalidValue));
         }
 
@@ -146,6 +178,8 @@ impl Args {
         if matches.occurrences_of("ast") > 0 {
             ast = true
         }
-         Ok(Args { bin_file_name, script_file_name, addr_start, addr_end, ast })
+        let symbols = matches.is_present("symbols");
+        let notation = Notation::from_name(matches.value_of("notation").unwrap()).unwrap();
+        Ok(Args { bin_file_name, script_file_name, addr_start, addr_end, ast, symbols, notation, memory_size })
     }
 }
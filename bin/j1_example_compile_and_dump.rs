@@ -10,18 +10,18 @@ fn main() -> std::io::Result<()> {
     println!("Forth script to compile and dump from j1e => `{}`", script.trim());
 
     let mut cpu = CPU::new();
-    cpu.load_bytes(&j1e_bin::J1E_BIN.to_vec()).unwrap();
-    let xs = cpu.dump_asm(addr_start, addr_end);
+    cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+    let xs = cpu.dump_asm(addr_start, addr_end)?;
     println!("\nmemory ASM from 0x{:04X} to 0x{:04X} before compile script: ", addr_start, addr_end);
     xs.iter().for_each(|x| println!("{}", x));
 
     cpu.run(script.bytes().collect()).unwrap();
     println!("\nmemory ASM from 0x{:04X} to 0x{:04X} after compile script: ", addr_start, addr_end);
-    let xs = cpu.dump_asm(addr_start, addr_end);
+    let xs = cpu.dump_asm(addr_start, addr_end)?;
     xs.iter().for_each(|x| println!("{}", x));
 
     println!("\nmemory AST from 0x{:04X} to 0x{:04X} after compile script: ", addr_start, addr_end);
-    let xs = cpu.dump_ast(addr_start, addr_end);
+    let xs = cpu.dump_ast(addr_start, addr_end)?;
     xs.iter().for_each(|x| println!("{}", x));
 
     Ok(())
@@ -0,0 +1,117 @@
+extern crate clap;
+
+use std::ffi::OsString;
+
+use clap::{App, Arg};
+
+use j1::cpu::CPU;
+use j1::gdb;
+use j1::j1e_bin;
+use j1::utils::{read_binary, read_forth_source};
+
+fn main() -> std::io::Result<()> {
+    let args = Args::new();
+
+    // stdout carries the protocol in stdio mode, so messages go to stderr
+    eprintln!("Starting j1_gdb...\n");
+
+    // read a j1 binary file
+    let binary = if args.bin_file_name.is_empty() {
+        eprintln!("loaded j1e binary");
+        j1e_bin::J1E_BIN.to_vec()
+    } else {
+        eprintln!("loaded binary: {}", args.bin_file_name);
+        read_binary(&args.bin_file_name)?
+    };
+
+    let mut cpu = CPU::new();
+    cpu.load_bytes(&binary)?;
+    cpu.stop_on_idle = true;
+
+    // queue a forth script file as console input
+    if !args.script_file_name.is_empty() {
+        let script = read_forth_source(&args.script_file_name)?;
+        eprintln!("queued script: {}", args.script_file_name);
+        cpu.load_input(script);
+    }
+
+    if args.stdio {
+        gdb::serve_stdio(cpu)?;
+    } else {
+        eprintln!("waiting for gdb on localhost:{}", args.port);
+        gdb::serve_tcp(cpu, args.port)?;
+    }
+    eprintln!("\nExiting j1_gdb...");
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub bin_file_name: String,
+    pub script_file_name: String,
+    pub port: u16,
+    pub stdio: bool,
+}
+
+#[allow(dead_code, clippy::new_without_default)]
+impl Args {
+    pub fn new() -> Self {
+        Self::new_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    fn new_from<I, T>(args: I) -> Result<Self, clap::Error>
+        where
+            I: Iterator<Item=T>,
+            T: Into<OsString> + Clone,
+    {
+        // basic app information
+        let app = App::new("j1_gdb")
+            .version("1.0")
+            .about("J1 cpu gdb remote serial protocol server")
+            .author("Roy Crippen");
+
+        // define the binary file name command line option
+        let bin_file_name_option = Arg::with_name("bin_file")
+            .long("bin") // allow --bin
+            .short("b") // allow -b
+            .takes_value(true)
+            .help("Binary J1 forth image to load, default is j1e.bin")
+            .required(false);
+
+        // define the forth script file name command line option
+        let script_file_name_option = Arg::with_name("script_file")
+            .long("script") // allow --script
+            .short("s") // allow -s
+            .takes_value(true)
+            .help("Forth script file to queue as console input")
+            .required(false);
+
+        // define the tcp port command line option
+        let port_option = Arg::with_name("port")
+            .long("port") // allow --port
+            .short("p") // allow -p
+            .takes_value(true)
+            .default_value("2331")
+            .help("Port to listen on, localhost only")
+            .required(false);
+
+        // define the stdio flag
+        let stdio_flag = Arg::with_name("stdio")
+            .long("stdio") // allow --stdio
+            .help("Serve gdb over stdin/stdout instead of tcp")
+            .required(false);
+
+        let app = app
+            .arg(bin_file_name_option)
+            .arg(script_file_name_option)
+            .arg(port_option)
+            .arg(stdio_flag);
+        let matches = app.get_matches_from_safe(args)?;
+        let bin_file_name = matches.value_of("bin_file").unwrap_or("").to_string();
+        let script_file_name = matches.value_of("script_file").unwrap_or("").to_string();
+        let port = matches.value_of("port").unwrap().parse::<u16>()
+            .map_err(|_e| clap::Error::with_description("Invalid port", clap::ErrorKind::InvalidValue))?;
+        let stdio = matches.occurrences_of("stdio") > 0;
+        Ok(Args { bin_file_name, script_file_name, port, stdio })
+    }
+}
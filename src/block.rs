@@ -0,0 +1,304 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::cpu::CPU;
+use crate::error::Error;
+use crate::instruction::{decode, Instruction};
+
+// longest block, also bounds the cells scanned when a write invalidates blocks
+pub const MAX_BLOCK_LEN: usize = 64;
+
+/// Which execution engine `CPU::resume` uses
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Engine {
+    // fetch, decode and execute one instruction at a time
+    #[default]
+    Interpreter,
+    // run cached basic blocks, one instruction at a time while breakpoints,
+    // a tracer, a history or a waveform are attached
+    Blocks,
+}
+
+/// Block
+///
+/// A basic block translated into a micro-op sequence: the decoded
+/// instructions from `start` through the first `Jump`, `Conditional`, `Call`
+/// or R→PC ALU instruction, at most `MAX_BLOCK_LEN` of them. Only the last
+/// micro-op can change the program counter other than by one.
+///
+/// # Example
+///
+/// ```
+/// use j1::block::Block;
+/// use j1::instruction::Instruction::{Jump, Literal};
+///
+/// // LIT 0001, LIT 0002, UBRANCH 0000, LIT 0003
+/// let memory = [0x8001, 0x8002, 0x0000, 0x8003];
+/// let block = Block::translate(&memory, 0).unwrap();
+/// assert_eq!(vec![Literal(1), Literal(2), Jump(0)], block.ops);
+/// assert!(block.contains(2) && !block.contains(3));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    pub ops: Vec<Instruction>,
+}
+
+impl Block {
+    /// Translates the block starting at cell `start`, `memory` is the code
+    /// the program counter can reach. Stops before a cell that does not
+    /// decode, which only fails if it is the first one.
+    pub fn translate(memory: &[u16], start: u16) -> Result<Block, Error> {
+        let mut ops = Vec::new();
+        let mut pc = start as usize;
+        while pc < memory.len() && ops.len() < MAX_BLOCK_LEN {
+            let ins = match decode(memory[pc]) {
+                Ok(ins) => ins,
+                Err(e) if ops.is_empty() => return Err(e),
+                Err(_) => break,
+            };
+            ops.push(ins);
+            pc += 1;
+            if ends_block(&ins) {
+                break;
+            }
+        }
+        Ok(Block { start, ops })
+    }
+
+    /// True if cell `cell` is one of the block's instructions
+    pub fn contains(&self, cell: usize) -> bool {
+        let start = self.start as usize;
+        start <= cell && cell < start + self.ops.len()
+    }
+}
+
+fn ends_block(ins: &Instruction) -> bool {
+    match ins {
+        Instruction::Literal(_) => false,
+        Instruction::ALU(alu) => alu.r2pc,
+        _ => true,
+    }
+}
+
+/// Translated blocks by start cell, shared with clones of the CPU until
+/// either one writes to code
+#[derive(Clone, Default)]
+pub(crate) struct BlockCache {
+    blocks: Vec<Option<Arc<Block>>>,
+    // cells that are or were part of a cached block
+    covered: Vec<bool>,
+}
+
+impl BlockCache {
+    // removes the block starting at `pc` while it runs, `put` returns it
+    pub(crate) fn take(&mut self, pc: usize) -> Option<Arc<Block>> {
+        self.blocks.get_mut(pc).and_then(|b| b.take())
+    }
+
+    pub(crate) fn put(&mut self, block: Arc<Block>) {
+        let start = block.start as usize;
+        self.blocks[start] = Some(block);
+    }
+
+    // covers the cells of a new block, `put` caches it
+    pub(crate) fn insert(&mut self, block: Block, code_size: usize) -> Arc<Block> {
+        if self.blocks.is_empty() {
+            self.blocks = vec![None; code_size];
+            self.covered = vec![false; code_size];
+        }
+        let start = block.start as usize;
+        self.covered[start..start + block.ops.len()].iter_mut().for_each(|c| *c = true);
+        Arc::new(block)
+    }
+
+    /// Drops every block containing `cell`
+    pub(crate) fn invalidate(&mut self, cell: usize) {
+        if !self.covered.get(cell).copied().unwrap_or(false) {
+            return;
+        }
+        for start in cell.saturating_sub(MAX_BLOCK_LEN - 1)..=cell {
+            if self.blocks[start].as_ref().is_some_and(|b| b.contains(cell)) {
+                self.blocks[start] = None;
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.blocks.clear();
+        self.covered.clear();
+    }
+}
+
+/// Where the block engine and the interpreter first disagree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    // cycle of the interpreter after the block that diverged
+    pub cycle: u64,
+    pub message: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "engines diverge at cycle {}: {}", self.cycle, self.message)
+    }
+}
+
+/// Differential test
+///
+/// Loads `input` into two copies of `cpu`, one running `Engine::Blocks` a
+/// block at a time and one running the interpreter instruction by
+/// instruction, and compares the complete machine state, stack events and
+/// results after every block. Runs until the input is consumed, or forth
+/// waits for input with `stop_on_idle`, an error or `max_instructions`.
+/// Returns the instructions executed.
+///
+/// # Example
+///
+/// ```
+/// use j1::block::differential;
+/// use j1::cpu::CPU;
+/// use j1::j1e_bin;
+///
+/// let mut cpu = CPU::builder().stop_on_idle(true).build().unwrap();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+///
+/// // compiling a word writes to code that has been translated
+/// let n = differential(&cpu, b": sq dup * ; 7 sq .\n", 10_000_000).unwrap();
+/// assert!(n > 0);
+/// ```
+pub fn differential(cpu: &CPU, input: &[u8], max_instructions: u64) -> Result<u64, Divergence> {
+    let mut blocks = cpu.clone();
+    blocks.engine = Engine::Blocks;
+    let mut interpreter = cpu.clone();
+    interpreter.engine = Engine::Interpreter;
+    for x in [&mut blocks, &mut interpreter].iter_mut() {
+        x.breakpoints = Default::default();
+        x.tracer = None;
+        x.history = None;
+        x.waveform = None;
+        x.load_input(input.to_vec());
+    }
+
+    let start = cpu.cycles();
+    while blocks.cycles() - start < max_instructions {
+        let expected = blocks.step_block().map(|_| ());
+        let mut result = Ok(());
+        while result.is_ok() && interpreter.cycles() < blocks.cycles() {
+            result = interpreter.step().map(|_| ());
+        }
+
+        let diverge = |message: String| Divergence { cycle: interpreter.cycles(), message };
+        if expected != result {
+            return Err(diverge(format!("blocks returned {:?}, interpreter {:?}", expected, result)));
+        }
+        if blocks.snapshot() != interpreter.snapshot() {
+            return Err(diverge(format!("state {:?}, interpreter {:?}", blocks.state(), interpreter.state())));
+        }
+        if blocks.stack_events != interpreter.stack_events {
+            return Err(diverge("stack events differ".to_string()));
+        }
+        if blocks.is_waiting_for_input() != interpreter.is_waiting_for_input() {
+            return Err(diverge("idle detection differs".to_string()));
+        }
+
+        let stopped = if cpu.stop_on_idle { blocks.is_waiting_for_input() } else { !blocks.console.has_input() };
+        if expected.is_err() || stopped {
+            break;
+        }
+    }
+    Ok(blocks.cycles() - start)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::*;
+    use crate::cpu::{StopReason, CPU};
+    use crate::instruction::Instruction::{ALU, Call, Conditional, Jump, Literal};
+    use crate::instruction::decode;
+    use crate::j1e_bin;
+
+    #[test]
+    fn translate() {
+        // LIT 0001, ALU T, 0BRANCH, CALL, exit, UBRANCH
+        let memory = [0x8001, 0x6000, 0x2010, 0x4020, 0x700C, 0x0000];
+        let test_cases = [
+            (0, vec![Literal(1), decode(0x6000).unwrap(), Conditional(0x10)]),
+            (3, vec![Call(0x20)]),
+            (4, vec![decode(0x700C).unwrap()]),
+            (5, vec![Jump(0)]),
+            (6, vec![]),
+        ];
+        for (start, ops) in test_cases.iter() {
+            assert_eq!(*ops, Block::translate(&memory, *start).unwrap().ops);
+        }
+
+        let memory = [0x6000; MAX_BLOCK_LEN + 1];
+        let block = Block::translate(&memory, 0).unwrap();
+        assert_eq!(MAX_BLOCK_LEN, block.ops.len());
+        assert!(block.ops.iter().all(|x| matches!(x, ALU(_))));
+    }
+
+    #[test]
+    fn differential_eforth() {
+        let script = b"2 3 + .s\ndecimal\n: fib dup 2 < if drop 1 else dup 2 - recurse swap 1 - recurse + then ;\n10 fib .\n";
+        for stop_on_idle in [true, false].iter() {
+            let mut cpu = CPU::builder().stop_on_idle(*stop_on_idle).build().unwrap();
+            cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+            assert!(differential(&cpu, script, 10_000_000).is_ok());
+        }
+
+        // both engines through resume
+        let mut logs = Vec::new();
+        for engine in [Engine::Interpreter, Engine::Blocks].iter() {
+            let mut cpu = CPU::builder().stop_on_idle(true).engine(*engine).build().unwrap();
+            cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+            assert_eq!(Ok(StopReason::WaitingForInput), cpu.run(script.to_vec()));
+            assert_eq!(Ok(StopReason::Bye), cpu.run(b"bye\n".to_vec()));
+            logs.push((cpu.console.get_log(), cpu.snapshot()));
+        }
+        assert_eq!(logs[0], logs[1]);
+        assert!(logs[1].0.contains("10 fib . 89 ok"));
+
+        // budgets stop both engines after the same instruction
+        let mut snapshots = Vec::new();
+        for engine in [Engine::Interpreter, Engine::Blocks].iter() {
+            let mut cpu = CPU::builder().engine(*engine).build().unwrap();
+            cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+            cpu.limits.per_call = Some(123_457);
+            assert!(cpu.run(b": hang begin again ; hang\n".to_vec()).is_err());
+            snapshots.push(cpu.snapshot());
+        }
+        assert_eq!(snapshots[0], snapshots[1]);
+        assert_eq!(123_457, snapshots[1].cycles);
+    }
+
+    #[test]
+    fn self_modifying() {
+        let mut cpu = CPU::builder().engine(Engine::Blocks).build().unwrap();
+        // LIT 6000, LIT 0008, N→[T] d-1, ALU T, LIT 0001, UBRANCH 0000
+        cpu.load_bytes(&[0x00, 0xE0, 0x08, 0x80, 0x23, 0x60, 0x00, 0x60, 0x01, 0x80, 0x00, 0x00]).unwrap();
+        // the store replaces LIT 0001 further down its own block with a NOOP
+        assert_eq!(Ok(3), cpu.step_block());
+        assert_eq!(Ok(3), cpu.step_block());
+        assert_eq!(8, cpu.state().t);
+        assert!(differential(&cpu, b"", 1000).is_ok());
+
+        cpu.write_memory(4, 0x8002);
+        cpu.set_pc(3);
+        assert_eq!(Ok(3), cpu.step_block());
+        assert_eq!(2, cpu.state().t);
+
+        // the same program through resume
+        let mut snapshots = Vec::new();
+        for engine in [Engine::Interpreter, Engine::Blocks].iter() {
+            let mut cpu = CPU::builder().engine(*engine).build().unwrap();
+            cpu.load_bytes(&[0x00, 0xE0, 0x08, 0x80, 0x23, 0x60, 0x00, 0x60, 0x01, 0x80, 0x00, 0x00]).unwrap();
+            cpu.limits.per_call = Some(100);
+            assert!(cpu.run(Vec::new()).is_err());
+            snapshots.push(cpu.snapshot());
+        }
+        assert_eq!(snapshots[0], snapshots[1]);
+        assert_eq!(8, snapshots[1].st0);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::cpu::MEMORY_SIZE;
+use crate::io::{Halt, IoBus};
+use crate::verilog::Timing;
+
+/// Board
+///
+/// A board profile: the memory size, timing model and i/o map an image is
+/// built for, see `CpuBuilder::board`
+///
+/// # Example
+///
+/// ```
+/// use j1::board::Board;
+/// use j1::cpu::CPU;
+///
+/// let mut cpu = CPU::builder().board(Board::PapilioPro).build().unwrap();
+/// assert_eq!(0x4000, cpu.memory().len());
+///
+/// // LIT 7801, DUP, +, 1-, @: reads the miniuart status register at 0xF001
+/// cpu.load_bytes(&[0x01, 0xF8, 0x81, 0x60, 0x03, 0x62, 0x00, 0x6A, 0x00, 0x6C]).unwrap();
+/// cpu.load_input(b"a".to_vec());
+/// cpu.step_n(5).unwrap();
+/// assert_eq!(1, cpu.state().t);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Board {
+    // resources/j1eforth/j1.c with 8K words: console tx!/rx@ at 0x7000, ?rx at
+    // 0x7001 and bye at 0x7002
+    #[default]
+    J1cSim,
+    // papilio-pro-j1.vhd: the j1.v core with 16K words and the miniuart data
+    // register at 0xF000, status at 0xF001 with bit 0 RxAv and bit 1 TxBusy
+    PapilioPro,
+}
+
+impl Board {
+    pub fn from_name(name: &str) -> Option<Board> {
+        match name {
+            "j1c-sim" => Some(Board::J1cSim),
+            "papilio-pro" => Some(Board::PapilioPro),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Board::J1cSim => "j1c-sim",
+            Board::PapilioPro => "papilio-pro",
+        }
+    }
+
+    /// Memory size in 16 bit cells
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Board::J1cSim => MEMORY_SIZE,
+            Board::PapilioPro => 0x4000,
+        }
+    }
+
+    pub fn timing(&self) -> Timing {
+        match self {
+            Board::J1cSim => Timing::Functional,
+            Board::PapilioPro => Timing::Verilog,
+        }
+    }
+
+    /// The board's devices, the console is the j1.c terminal or the UART
+    pub fn io(&self) -> IoBus {
+        let mut io = IoBus::empty();
+        match self {
+            Board::J1cSim => {
+                io.map_console(0x7000, 0x7001);
+                io.map(0x7002, 0x7002, Box::new(Halt));
+            }
+            Board::PapilioPro => io.map_console(0xF000, 0xF001),
+        }
+        io
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::console::Console;
+    use crate::cpu::{StopReason, CPU};
+    use crate::error::Error;
+
+    #[test]
+    fn io_maps() {
+        let mut console = Console::new();
+        console.load(&mut b"a".to_vec());
+
+        let mut io = Board::PapilioPro.io();
+        let test_cases = [(0xF001, 1), (0xF000, 'a' as u16), (0xF001, 0), (0x7001, 0), (0x7000, 0), (0xF002, 0)];
+        for (addr, expected) in test_cases.iter() {
+            assert_eq!(Ok(*expected), io.read(&mut console, *addr));
+        }
+        assert_eq!(Ok(()), io.write(&mut console, 0x7002, 0));
+        assert_eq!(Some(0xF001), io.console_status());
+
+        let mut io = Board::J1cSim.io();
+        assert_eq!(Err(Error::Bye), io.write(&mut console, 0x7002, 0));
+        assert_eq!(Some(0x7001), io.console_status());
+
+        // forth polling ?rx on the UART with no input is idle
+        let mut cpu = CPU::builder().board(Board::PapilioPro).stop_on_idle(true).build().unwrap();
+        // LIT 7801, DUP, +, 1-, @, 0BRANCH 0000
+        cpu.load_bytes(&[0x01, 0xF8, 0x81, 0x60, 0x03, 0x62, 0x00, 0x6A, 0x00, 0x6C, 0x00, 0x20]).unwrap();
+        assert_eq!(Ok(StopReason::WaitingForInput), cpu.resume());
+        assert_eq!(11, cpu.cycles());
+
+        for board in [Board::J1cSim, Board::PapilioPro].iter() {
+            assert_eq!(Some(*board), Board::from_name(board.name()));
+        }
+        assert_eq!(None, Board::from_name("icestorm"));
+    }
+}
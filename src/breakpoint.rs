@@ -0,0 +1,229 @@
+use crate::cpu::{Access, StepResult};
+use crate::instruction::{Instruction, OpCode};
+
+/// Instruction matcher for `Breakpoint::Instruction`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstructionPattern {
+    // exact decoded instruction, e.g. Call(0x004E)
+    Exact(Instruction),
+    // any instruction of the given kind
+    AnyLiteral,
+    AnyJump,
+    AnyConditional,
+    AnyCall,
+    AnyAlu,
+    // any ALU instruction with the given Tʹ operation
+    AluOp(OpCode),
+}
+
+impl InstructionPattern {
+    pub fn matches(&self, ins: &Instruction) -> bool {
+        match (self, ins) {
+            (InstructionPattern::Exact(x), _) => x == ins,
+            (InstructionPattern::AnyLiteral, Instruction::Literal(_)) => true,
+            (InstructionPattern::AnyJump, Instruction::Jump(_)) => true,
+            (InstructionPattern::AnyConditional, Instruction::Conditional(_)) => true,
+            (InstructionPattern::AnyCall, Instruction::Call(_)) => true,
+            (InstructionPattern::AnyAlu, Instruction::ALU(_)) => true,
+            (InstructionPattern::AluOp(op), Instruction::ALU(alu)) => alu.opcode == *op,
+            _ => false,
+        }
+    }
+}
+
+/// Condition that stops execution
+///
+/// `Pc` uses cell addresses like the program counter, the memory and I/O
+/// watchpoints use byte addresses as seen by `@` and `!`.
+/// `Pc` and `Instruction` stop before the instruction executes,
+/// the watchpoints stop right after the access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    Pc(u16),
+    Read(u16),
+    Write(u16),
+    Io(u16),
+    Instruction(InstructionPattern),
+}
+
+/// Breakpoint that stopped execution
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BreakpointHit {
+    pub id: usize,
+    pub breakpoint: Breakpoint,
+    // program counter of the instruction that triggered the hit
+    pub pc: u16,
+    // memory or I/O access for watchpoints
+    pub access: Option<Access>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Entry {
+    id: usize,
+    breakpoint: Breakpoint,
+    enabled: bool,
+}
+
+/// Breakpoints
+///
+/// Set of breakpoints and watchpoints checked by the CPU while running
+///
+/// # Example
+///
+/// ```
+/// use j1::breakpoint::Breakpoint;
+/// use j1::cpu::{StopReason, CPU};
+/// use j1::j1e_bin;
+///
+/// let mut cpu = CPU::new();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+///
+/// // stop on the first character written to the console
+/// let id = cpu.breakpoints.add(Breakpoint::Io(0x7000));
+/// match cpu.run(b"2 3 * .\n".to_vec()).unwrap() {
+///     StopReason::Breakpoint(hit) => assert_eq!(id, hit.id),
+///     reason => panic!("unexpected stop: {:?}", reason),
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Breakpoints {
+    entries: Vec<Entry>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    /// Adds a breakpoint and returns its id
+    pub fn add(&mut self, breakpoint: Breakpoint) -> usize {
+        self.next_id += 1;
+        self.entries.push(Entry { id: self.next_id, breakpoint, enabled: true });
+        self.next_id
+    }
+
+    /// Removes a breakpoint, returns false if the id is unknown
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.id != id);
+        len != self.entries.len()
+    }
+
+    /// Enables or disables a breakpoint, returns false if the id is unknown
+    pub fn enable(&mut self, id: usize, enabled: bool) -> bool {
+        match self.entries.iter_mut().find(|e| e.id == id) {
+            Some(e) => {
+                e.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// List of (id, breakpoint, enabled)
+    pub fn list(&self) -> Vec<(usize, Breakpoint, bool)> {
+        self.entries.iter().map(|e| (e.id, e.breakpoint, e.enabled)).collect()
+    }
+
+    /// Checks the breakpoints that fire before `ins` at `pc` executes
+    pub fn check_before(&self, pc: u16, ins: &Instruction) -> Option<BreakpointHit> {
+        self.entries.iter()
+            .filter(|e| e.enabled)
+            .find(|e| match e.breakpoint {
+                Breakpoint::Pc(addr) => addr == pc,
+                Breakpoint::Instruction(pattern) => pattern.matches(ins),
+                _ => false,
+            })
+            .map(|e| BreakpointHit { id: e.id, breakpoint: e.breakpoint, pc, access: None })
+    }
+
+    /// Checks the watchpoints triggered by an executed instruction
+    pub fn check_after(&self, step: &StepResult) -> Option<BreakpointHit> {
+        let hit = |e: &Entry, access: Option<Access>| {
+            BreakpointHit { id: e.id, breakpoint: e.breakpoint, pc: step.pc, access }
+        };
+        for e in self.entries.iter().filter(|e| e.enabled) {
+            match (e.breakpoint, step.read, step.write) {
+                (Breakpoint::Read(a), Some(Access::Memory { addr, .. }), _) if a == addr => return Some(hit(e, step.read)),
+                (Breakpoint::Write(a), _, Some(Access::Memory { addr, .. })) if a == addr => return Some(hit(e, step.write)),
+                (Breakpoint::Io(a), Some(Access::Io { addr, .. }), _) if a == addr => return Some(hit(e, step.read)),
+                (Breakpoint::Io(a), _, Some(Access::Io { addr, .. })) if a == addr => return Some(hit(e, step.write)),
+                _ => (),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::breakpoint::*;
+    use crate::instruction::AluAttributes;
+    use crate::instruction::Instruction::{ALU, Call, Literal};
+
+    #[test]
+    fn add_remove_enable() {
+        let mut bps = Breakpoints::default();
+        assert!(bps.is_empty());
+        let a = bps.add(Breakpoint::Pc(10));
+        let b = bps.add(Breakpoint::Write(0x20));
+        assert_ne!(a, b);
+        assert_eq!(vec![(a, Breakpoint::Pc(10), true), (b, Breakpoint::Write(0x20), true)], bps.list());
+
+        assert!(bps.enable(a, false));
+        assert!(bps.check_before(10, &Literal(0)).is_none());
+        assert!(bps.enable(a, true));
+        assert!(bps.check_before(10, &Literal(0)).is_some());
+
+        assert!(bps.remove(a));
+        assert!(!bps.remove(a));
+        assert_eq!(1, bps.list().len());
+        bps.clear();
+        assert!(bps.is_empty());
+    }
+
+    #[test]
+    fn instruction_pattern() {
+        let alu = ALU(AluAttributes { opcode: OpCode::OpAtT, ..AluAttributes::default() });
+        let test_cases = [
+            (InstructionPattern::Exact(Call(0x4e)), Call(0x4e), true),
+            (InstructionPattern::Exact(Call(0x4e)), Call(0x4f), false),
+            (InstructionPattern::AnyCall, Call(0x4f), true),
+            (InstructionPattern::AnyCall, Literal(0x4f), false),
+            (InstructionPattern::AnyLiteral, Literal(0x4f), true),
+            (InstructionPattern::AnyAlu, alu, true),
+            (InstructionPattern::AluOp(OpCode::OpAtT), alu, true),
+            (InstructionPattern::AluOp(OpCode::OpT), alu, false),
+        ];
+        for (pattern, ins, expected) in test_cases.iter() {
+            assert_eq!(*expected, pattern.matches(ins));
+        }
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut bps = Breakpoints::default();
+        let r = bps.add(Breakpoint::Read(0x10));
+        let w = bps.add(Breakpoint::Write(0x10));
+        let io = bps.add(Breakpoint::Io(0x7000));
+
+        let mut step = StepResult { instruction: Literal(0), pc: 5, next_pc: 6, read: None, write: None };
+        assert!(bps.check_after(&step).is_none());
+
+        step.read = Some(Access::Memory { addr: 0x10, value: 1 });
+        assert_eq!(Some(r), bps.check_after(&step).map(|h| h.id));
+
+        step.read = None;
+        step.write = Some(Access::Memory { addr: 0x10, value: 1 });
+        let hit = bps.check_after(&step).unwrap();
+        assert_eq!((w, 5, step.write), (hit.id, hit.pc, hit.access));
+
+        step.write = Some(Access::Io { addr: 0x7000, value: 65 });
+        assert_eq!(Some(io), bps.check_after(&step).map(|h| h.id));
+    }
+}
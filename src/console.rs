@@ -14,6 +14,12 @@ pub struct Console {
     pub log: Vec<char>,
 }
 
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Console {
     /// Constructs a new, empty `Console`.
     /// # Examples
@@ -39,6 +45,11 @@ impl Console {
         }
     }
 
+    /// True if the read buffer has unread bytes
+    pub fn has_input(&self) -> bool {
+        self.reader.position() < self.reader.get_ref().len() as u64
+    }
+
     pub fn read_char(&mut self) -> char {
         let _r = self.reader.read_exact(&mut self.ar1);
         self.ar1[0] as char
@@ -104,6 +115,12 @@ mod tests {
         let ys = console.reader.get_ref();
         assert_eq!(xs.len(), ys.len());
         assert_eq!(xs, *ys);
+
+        assert!(console.has_input());
+        for _ in xs.iter() {
+            console.read_char();
+        }
+        assert!(!console.has_input());
     }
 }
 
@@ -1,14 +1,100 @@
+use std::time::{Duration, Instant};
+
 use byteorder::{LittleEndian, ReadBytesExt};
 
+use crate::block::{Block, BlockCache, Engine};
+use crate::board::Board;
+use crate::breakpoint::{BreakpointHit, Breakpoints};
 use crate::console::Console;
-use crate::instruction::{decode, Instruction, OpCode};
+use crate::dictionary::Dictionary;
+use crate::disasm::{classify, counted, quoted, CellKind};
+use crate::error::{Budget, Error};
+use crate::instruction::{decode, AluAttributes, Instruction, Notation, OpCode};
 use crate::instruction::Instruction::{ALU, Call, Conditional, Jump, Literal};
-use crate::stack::Stack;
-use std::io::{ErrorKind, Error};
+use crate::history::History;
+use crate::io::IoBus;
+use crate::snapshot::{ConsoleState, Snapshot};
+use crate::stack::{Stack, StackEvent, StackKind, StackPolicy};
+use crate::trace::Tracer;
+use crate::vcd::{Signals, Waveform};
+use crate::verilog::{Bus, Timing};
 
 const IO_MASK: u16 = 3 << 14;
+// instructions between wall-clock checks
+const TIME_CHECK_INTERVAL: u64 = 0x400;
+// default memory size in cells, the 8K word j1.v configuration
 pub const MEMORY_SIZE: usize = 0x2000;
-// pub const MEMORY_SIZE: usize = 0x4000;
+// the whole 16 bit byte address space
+pub const MAX_MEMORY_SIZE: usize = 0x8000;
+// cells addressed by the 13 bit program counter
+const CODE_SIZE: usize = 0x2000;
+const PC_MASK: u16 = 0x1fff;
+// cell holding the size of a file mapped with `CPU::map_file`, contents follow
+pub const MAP_FILE_CELL: usize = 0x2000;
+
+/// A memory or I/O access made by an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Memory { addr: u16, value: u16 },
+    Io { addr: u16, value: u16 },
+}
+
+/// What happened while executing a single instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepResult {
+    pub instruction: Instruction,
+    // program counter before and after execution
+    pub pc: u16,
+    pub next_pc: u16,
+    // value read by [T]
+    pub read: Option<Access>,
+    // value written by N→[T]
+    pub write: Option<Access>,
+}
+
+// what an executed instruction did that can end a run of the fast loops
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Effect {
+    None,
+    // wrote a memory cell
+    Write(usize),
+    // read or wrote an i/o device
+    Io,
+}
+
+/// Snapshot of the CPU registers and stacks, stacks listed bottom to top
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct CpuState {
+    pub pc: u16,
+    pub t: u16,
+    pub data_stack: Vec<u16>,
+    pub return_stack: Vec<u16>,
+    pub data_depth: u16,
+    pub return_depth: u16,
+}
+
+/// Why `CPU::run` returned without an error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    // all console input has been read
+    InputConsumed,
+    // `?rx` polled an empty console, only with `CPU::stop_on_idle`
+    WaitingForInput,
+    Breakpoint(BreakpointHit),
+    // `bye` was executed
+    Bye,
+}
+
+/// Execution limits checked by `CPU::run` and `CPU::resume`, None is unlimited
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Limits {
+    // total instructions executed by the CPU, see `CPU::cycles`
+    pub max_instructions: Option<u64>,
+    // instructions executed by a single run or resume call
+    pub per_call: Option<u64>,
+    // wall-clock time of a single run or resume call
+    pub max_time: Option<Duration>,
+}
 
 /// CPU
 ///
@@ -36,8 +122,16 @@ pub const MEMORY_SIZE: usize = 0x2000;
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct CPU {
-    // 0..0x3fff RAM, 0x4000..0x7fff mem-mapped I/O
-    memory: Box<[u16; MEMORY_SIZE]>,
+    // RAM cells, byte addresses past the end are mem-mapped I/O. With more
+    // than 0x2000 cells, 0x4000.. is RAM except where an i/o device is mapped
+    memory: Box<[u16]>,
+
+    // decoded instructions for the cells the program counter can reach,
+    // filled on fetch and cleared when a cell is written
+    decoded: Box<[Option<Instruction>]>,
+
+    // basic blocks translated by the Blocks engine
+    blocks: BlockCache,
 
     // 13 bit program counter
     pc: u16,
@@ -53,77 +147,941 @@ pub struct CPU {
 
     // io console
     pub console: Console,
+
+    // breakpoints and watchpoints checked by run
+    pub breakpoints: Breakpoints,
+
+    // instruction trace recorder
+    pub tracer: Option<Tracer>,
+
+    // checkpoints and input for reverse execution
+    pub history: Option<History>,
+
+    // j1.v signals of every clock, for a VCD file or test vectors
+    pub waveform: Option<Waveform>,
+
+    // memory mapped i/o devices, the console is mapped at 0x7000 by default,
+    // see `Board`
+    pub io: IoBus,
+
+    // execution limits for run
+    pub limits: Limits,
+
+    // run stops with WaitingForInput when ?rx polls an empty console,
+    // instead of InputConsumed as soon as the last byte is read
+    pub stop_on_idle: bool,
+
+    // what an overflow or underflow of each stack does
+    pub data_policy: StackPolicy,
+    pub return_policy: StackPolicy,
+
+    // track stack levels and water marks with StackPolicy::Wrap too
+    pub water_marks: bool,
+
+    // overflows and underflows recorded with StackPolicy::Warn
+    pub stack_events: Vec<StackEvent>,
+
+    // execution engine used by run and resume
+    pub engine: Engine,
+
+    // functional or j1.v model of memory reads and i/o decoding
+    pub timing: Timing,
+
+    // RAM output register of j1.v, [T] reads it with Timing::Verilog
+    mem_din: u16,
+
+    // instructions executed since the CPU was created
+    cycles: u64,
+
+    // stack levels are tracked, see update_tracking
+    track_stacks: bool,
+
+    // the last instruction polled ?rx on an empty console, see track_idle
+    idle: bool,
+    // cell address of the last ?rx read of an empty console since the last
+    // i/o write
+    rx_poll: Option<u16>,
 }
 
-#[allow(dead_code)]
-impl CPU {
-    pub fn new() -> Self {
-        CPU {
-            memory: Box::new([0u16; MEMORY_SIZE]),
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CpuBuilder
+///
+/// Construction time configuration for a `CPU`
+///
+/// # Example
+///
+/// ```
+/// use j1::cpu::CPU;
+/// use j1::j1e_bin;
+///
+/// // 16K words like resources/j1eforth/j1.c
+/// let mut cpu = CPU::builder().memory_size(0x4000).stop_on_idle(true).build().unwrap();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+/// assert_eq!(0x4000, cpu.memory().len());
+///
+/// // the upper half is memory, except the console at 0x7000
+/// cpu.run(b"1234 4000 ! 4000 @ .\n".to_vec()).unwrap();
+/// assert!(cpu.console.get_log().ends_with(" 1234 ok\n"));
+/// assert_eq!(0x1234, cpu.memory()[0x2000]);
+///
+/// assert!(CPU::builder().memory_size(0x10000).build().is_err());
+/// ```
+#[derive(Clone)]
+pub struct CpuBuilder {
+    memory_size: usize,
+    io: IoBus,
+    limits: Limits,
+    stop_on_idle: bool,
+    data_policy: StackPolicy,
+    return_policy: StackPolicy,
+    water_marks: bool,
+    engine: Engine,
+    timing: Timing,
+}
+
+impl Default for CpuBuilder {
+    fn default() -> Self {
+        CpuBuilder {
+            memory_size: MEMORY_SIZE,
+            io: IoBus::default(),
+            limits: Limits::default(),
+            stop_on_idle: false,
+            data_policy: StackPolicy::default(),
+            return_policy: StackPolicy::default(),
+            water_marks: false,
+            engine: Engine::default(),
+            timing: Timing::default(),
+        }
+    }
+}
+
+impl CpuBuilder {
+    /// Memory size in 16 bit cells, `MEMORY_SIZE` through `MAX_MEMORY_SIZE`
+    pub fn memory_size(mut self, cells: usize) -> Self {
+        self.memory_size = cells;
+        self
+    }
+
+    pub fn io(mut self, io: IoBus) -> Self {
+        self.io = io;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn stop_on_idle(mut self, stop_on_idle: bool) -> Self {
+        self.stop_on_idle = stop_on_idle;
+        self
+    }
+
+    pub fn stack_policy(mut self, stack: StackKind, policy: StackPolicy) -> Self {
+        match stack {
+            StackKind::Data => self.data_policy = policy,
+            StackKind::Return => self.return_policy = policy,
+        }
+        self
+    }
+
+    pub fn water_marks(mut self, water_marks: bool) -> Self {
+        self.water_marks = water_marks;
+        self
+    }
+
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Memory size, timing and i/o map of `board`, later calls override them
+    pub fn board(self, board: Board) -> Self {
+        self.memory_size(board.memory_size()).timing(board.timing()).io(board.io())
+    }
+
+    pub fn build(self) -> std::io::Result<CPU> {
+        // the 13 bit program counter must stay inside memory
+        if self.memory_size < MEMORY_SIZE || self.memory_size > MAX_MEMORY_SIZE {
+            return Err(std::io::Error::other(format!(
+                "Memory size 0x{:X} cells must be from 0x{:X} to 0x{:X}",
+                self.memory_size, MEMORY_SIZE, MAX_MEMORY_SIZE)));
+        }
+        Ok(CPU {
+            memory: vec![0u16; self.memory_size].into_boxed_slice(),
+            decoded: vec![None; CODE_SIZE].into_boxed_slice(),
+            blocks: BlockCache::default(),
             pc: 0,
             st0: 0,
             d: Stack::default(),
             r: Stack::default(),
             console: Console::new(),
-        }
+            breakpoints: Breakpoints::default(),
+            tracer: None,
+            history: None,
+            waveform: None,
+            io: self.io,
+            limits: self.limits,
+            stop_on_idle: self.stop_on_idle,
+            data_policy: self.data_policy,
+            return_policy: self.return_policy,
+            water_marks: self.water_marks,
+            stack_events: Vec::new(),
+            engine: self.engine,
+            timing: self.timing,
+            mem_din: 0,
+            cycles: 0,
+            track_stacks: false,
+            idle: false,
+            rx_poll: None,
+        })
+    }
+}
+
+#[allow(dead_code)]
+impl CPU {
+    /// CPU with `MEMORY_SIZE` cells and the j1eforth console
+    pub fn new() -> Self {
+        CpuBuilder::default().build().unwrap()
+    }
+
+    pub fn builder() -> CpuBuilder {
+        CpuBuilder::default()
     }
 
-    pub fn run(&mut self, mut commands: Vec<u8>) -> Result<(), String> {
-        commands.push(b' ');
+    /// Loads `commands` into the console and runs until the input is consumed,
+    /// or forth waits for more input with `stop_on_idle`, or a breakpoint is hit
+    pub fn run(&mut self, mut commands: Vec<u8>) -> Result<StopReason, Error> {
+        // padding so the last command is evaluated before the input is consumed
+        if !self.stop_on_idle {
+            commands.push(b' ');
+        }
         // commands.push(b'\n');
-        self.console.load(&mut commands);
+        self.load_input(commands);
+        self.resume()
+    }
+
+    /// Continues running on the current console input, a breakpoint at
+    /// the current program counter is stepped over
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use j1::cpu::CPU;
+    /// use j1::error::{Budget, Error};
+    /// use j1::j1e_bin;
+    ///
+    /// let mut cpu = CPU::new();
+    /// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+    /// cpu.limits.per_call = Some(1_000_000);
+    ///
+    /// // an endless loop fails instead of hanging
+    /// let result = cpu.run(b": hang begin again ; hang\n".to_vec());
+    /// assert_eq!(Err(Error::BudgetExhausted(Budget::PerCall(1_000_000))), result);
+    /// ```
+    pub fn resume(&mut self) -> Result<StopReason, Error> {
+        self.update_tracking();
+        let start = Instant::now();
+        let mut count = 0u64;
+        let mut time_check = 0u64;
+        let mut step_over = true;
+        let observed = self.observed();
         loop {
-            let instruction = self.fetch().or_else(|e| Err(e))?;
-            self.execute(&instruction)?;
-            if self.console.reader.position() == self.console.reader.get_ref().len() as u64 {
+            let max = self.budget(count, &start, &mut time_check)?;
+            let mut n = 0;
+            while n < max {
+                let result = match self.engine {
+                    _ if observed => self.step_break(step_over).map(|_| 1),
+                    Engine::Blocks => self.run_blocks(max - n),
+                    Engine::Interpreter => self.run_fast(max - n),
+                };
+                match result {
+                    Ok(_) if self.stop_on_idle && self.idle => return Ok(StopReason::WaitingForInput),
+                    Ok(k) => n += k,
+                    Err(Error::Breakpoint(hit)) => return Ok(StopReason::Breakpoint(hit)),
+                    Err(Error::Bye) => return Ok(StopReason::Bye),
+                    Err(e) => return Err(e),
+                }
+                step_over = false;
+                if !self.stop_on_idle && !self.console.has_input() {
+                    return Ok(StopReason::InputConsumed);
+                }
+            }
+            count += n;
+        }
+    }
+
+    // instructions the current call may run before the limits are checked
+    // again, `count` instructions have run since `start` and the clock is
+    // read again once `count` reaches `time_check`
+    fn budget(&self, count: u64, start: &Instant, time_check: &mut u64) -> Result<u64, Error> {
+        let limits = &self.limits;
+        let mut max = u64::MAX;
+        if let Some(n) = limits.max_instructions {
+            if self.cycles >= n {
+                return Err(Error::BudgetExhausted(Budget::Instructions(n)));
+            }
+            max = n - self.cycles;
+        }
+        if let Some(n) = limits.per_call {
+            if count >= n {
+                return Err(Error::BudgetExhausted(Budget::PerCall(n)));
+            }
+            max = max.min(n - count);
+        }
+        if let Some(d) = limits.max_time {
+            if count >= *time_check {
+                *time_check = count + TIME_CHECK_INTERVAL;
+                if start.elapsed() >= d {
+                    return Err(Error::BudgetExhausted(Budget::Time(d)));
+                }
+            }
+            max = max.min(*time_check - count);
+        }
+        Ok(max)
+    }
+
+    /// Instructions executed since the CPU was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // true if breakpoints, a tracer, a history or a waveform need every
+    // instruction as a step result
+    fn observed(&self) -> bool {
+        !self.breakpoints.is_empty() || self.tracer.is_some() || self.history.is_some() || self.waveform.is_some()
+    }
+
+    // true if resume runs blocks, false when it has to step one instruction
+    // at a time
+    fn blocks_enabled(&self) -> bool {
+        self.engine == Engine::Blocks && !self.observed()
+    }
+
+    // runs at most `max` instructions without step results, stopping early
+    // after an i/o access, which can stop resume
+    fn run_fast(&mut self, max: u64) -> Result<u64, Error> {
+        self.idle = false;
+        if self.timing != Timing::Functional || self.track_stacks {
+            let mut n = 0;
+            while n < max {
+                let ins = self.fetch()?;
+                self.cycles += 1;
+                n += 1;
+                if self.exec(&ins)? == Effect::Io {
+                    break;
+                }
+            }
+            return Ok(n);
+        }
+
+        // nothing else reads the program counter, T or the cycle count until
+        // the loop stops, they stay in locals until then
+        let (mut pc, mut t) = (self.pc, self.st0);
+        let mut n = 0;
+        let result = loop {
+            if n == max {
+                break Ok(());
+            }
+            let ins = match self.decoded.get(pc as usize) {
+                Some(Some(ins)) => *ins,
+                _ => {
+                    self.pc = pc;
+                    match self.decode_at_pc() {
+                        Ok(ins) => ins,
+                        Err(e) => break Err(e),
+                    }
+                }
+            };
+            n += 1;
+            match self.exec_functional(&ins, &mut pc, &mut t) {
+                Ok(Effect::Io) => break Ok(()),
+                Ok(_) => (),
+                Err(e) => break Err(e),
+            }
+        };
+        self.pc = pc;
+        self.st0 = t;
+        self.cycles += n;
+        result.map(|_| n)
+    }
+
+    /// Runs the basic block at the program counter with `Engine::Blocks`, or
+    /// one instruction with the interpreter or while breakpoints, a tracer,
+    /// a history or a waveform are attached. Returns the instructions executed.
+    pub fn step_block(&mut self) -> Result<u64, Error> {
+        self.update_tracking();
+        if self.blocks_enabled() {
+            self.run_block(u64::MAX).map(|(n, _)| n)
+        } else {
+            self.step_break(true).map(|_| 1)
+        }
+    }
+
+    // runs blocks until `max` instructions have run or one stops after an
+    // i/o access, which can stop resume
+    fn run_blocks(&mut self, max: u64) -> Result<u64, Error> {
+        if self.timing != Timing::Functional || self.track_stacks {
+            let mut n = 0;
+            while n < max {
+                let (k, effect) = self.run_block(max - n)?;
+                n += k;
+                if effect == Effect::Io {
+                    break;
+                }
+            }
+            return Ok(n);
+        }
+
+        // like run_fast, the program counter, T and the cycle count stay in
+        // locals until the loop stops
+        self.idle = false;
+        let (mut pc, mut t) = (self.pc, self.st0);
+        let mut n = 0;
+        let result = 'run: loop {
+            if n == max {
+                break Ok(());
+            }
+            let block = match self.blocks.take(pc as usize) {
+                Some(block) => block,
+                None if (pc as usize) < self.decoded.len() => {
+                    let code = &self.memory[..self.decoded.len()];
+                    match Block::translate(code, pc) {
+                        Ok(block) => self.blocks.insert(block, code.len()),
+                        Err(e) => break Err(e),
+                    }
+                }
+                None => {
+                    (self.pc, self.st0) = (pc, t);
+                    self.cycles += n;
+                    return self.run_fast(1).map(|k| n + k);
+                }
+            };
+            let len = block.ops.len().min((max - n) as usize);
+            for ins in block.ops[..len].iter() {
+                n += 1;
+                match self.exec_functional(ins, &mut pc, &mut t) {
+                    Ok(Effect::Write(cell)) if block.contains(cell) => continue 'run,
+                    Ok(Effect::Io) => {
+                        self.blocks.put(block);
+                        break 'run Ok(());
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        self.blocks.put(block);
+                        break 'run Err(e);
+                    }
+                }
+            }
+            self.blocks.put(block);
+        };
+        self.pc = pc;
+        self.st0 = t;
+        self.cycles += n;
+        result.map(|_| n)
+    }
+
+    // runs at most `max` instructions of the block at the program counter,
+    // stopping early after an i/o access or a write to the block itself,
+    // which drops it. Returns the instructions executed and the effect of
+    // the last one.
+    fn run_block(&mut self, max: u64) -> Result<(u64, Effect), Error> {
+        let pc = self.pc as usize;
+        let block = match self.blocks.take(pc) {
+            Some(block) => block,
+            None if pc < self.decoded.len() => {
+                let code = &self.memory[..self.decoded.len()];
+                let block = Block::translate(code, self.pc)?;
+                self.blocks.insert(block, code.len())
+            }
+            None => return self.run_fast(1).map(|n| (n, Effect::Io)),
+        };
+        self.idle = false;
+        let mut n = 0;
+        let mut effect = Effect::None;
+        for ins in block.ops.iter() {
+            if n == max {
                 break;
             }
+            self.cycles += 1;
+            n += 1;
+            effect = match self.exec(ins) {
+                Ok(effect) => effect,
+                Err(e) => {
+                    self.blocks.put(block);
+                    return Err(e);
+                }
+            };
+            match effect {
+                Effect::Io => break,
+                Effect::Write(cell) if block.contains(cell) => return Ok((n, effect)),
+                _ => (),
+            }
+        }
+        self.blocks.put(block);
+        Ok((n, effect))
+    }
+
+    /// Like `step`, but fails with `Error::Breakpoint` when a breakpoint or
+    /// watchpoint is hit, `step_over` ignores breakpoints at the current pc
+    pub fn step_break(&mut self, step_over: bool) -> Result<StepResult, Error> {
+        let instruction = self.fetch()?;
+        if self.breakpoints.is_empty() {
+            return self.execute_traced(&instruction);
+        }
+        if !step_over {
+            if let Some(hit) = self.breakpoints.check_before(self.pc, &instruction) {
+                return Err(Error::Breakpoint(hit));
+            }
+        }
+        let step = self.execute_traced(&instruction)?;
+        match self.breakpoints.check_after(&step) {
+            Some(hit) => Err(Error::Breakpoint(hit)),
+            None => Ok(step),
         }
-        Ok(())
     }
 
-    fn fetch(&self) -> Result<Instruction, String> {
-        decode(self.memory[self.pc as usize])
+    /// Fetches, decodes and executes the instruction at the program counter
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use j1::cpu::CPU;
+    /// use j1::instruction::Instruction;
+    ///
+    /// let mut cpu = CPU::new();
+    /// cpu.load_bytes(&vec![0x2a, 0x80]).unwrap(); // LIT 002A
+    ///
+    /// let step = cpu.step().unwrap();
+    /// assert_eq!(Instruction::Literal(0x2a), step.instruction);
+    /// assert_eq!((0, 1), (step.pc, step.next_pc));
+    /// assert_eq!(0x2a, cpu.state().t);
+    /// ```
+    pub fn step(&mut self) -> Result<StepResult, Error> {
+        self.update_tracking();
+        let instruction = self.fetch()?;
+        self.execute_traced(&instruction)
+    }
+
+    /// Executes `n` instructions, stopping early on the first error
+    pub fn step_n(&mut self, n: usize) -> Result<Vec<StepResult>, Error> {
+        let mut steps = Vec::with_capacity(n);
+        for _ in 0..n {
+            steps.push(self.step()?);
+        }
+        Ok(steps)
     }
 
-    fn execute(&mut self, ins: &Instruction) -> Result<(), String> {
-        self.pc += 1;
+    /// Read-only copy of the registers and stacks
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            t: self.st0,
+            data_stack: self.d.dump(),
+            return_stack: self.r.dump(),
+            data_depth: self.d.depth(),
+            return_depth: self.r.depth(),
+        }
+    }
+
+    /// The j1.v i/o port outputs while the instruction at the program counter
+    /// executes, see `Bus`
+    pub fn bus(&self) -> Bus {
+        let insn = self.memory[self.pc as usize];
+        Bus::new(insn, self.st0, self.d.peek())
+    }
+
+    /// The j1.v registers and i/o port outputs while the instruction at the
+    /// program counter executes
+    pub fn signals(&self) -> Signals {
+        Signals {
+            cycle: self.cycles,
+            pc: self.pc,
+            insn: self.memory[self.pc as usize],
+            st0: self.st0,
+            dsp: self.d.sp as u8,
+            rsp: self.r.sp as u8,
+            bus: self.bus(),
+        }
+    }
+
+    /// Read-only view of memory, indexed by cell address
+    pub fn memory(&self) -> &[u16] {
+        &self.memory[..]
+    }
+
+    /// Writes a memory cell directly, bypassing memory mapped I/O
+    pub fn write_memory(&mut self, cell: usize, value: u16) {
+        self.memory[cell] = value;
+        self.invalidate(cell);
+        self.latch();
+        self.record_edit();
+    }
+
+    /// Sets the 13 bit program counter, a cell address
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc & PC_MASK;
+        self.record_edit();
+    }
+
+    pub fn set_t(&mut self, t: u16) {
+        self.st0 = t;
+        self.latch();
+        self.record_edit();
+    }
+
+    /// Replaces the console input, recorded in the history
+    pub fn load_input(&mut self, mut xs: Vec<u8>) {
+        self.console.load(&mut xs);
+        if let Some(mut history) = self.history.take() {
+            history.record_input(self);
+            self.history = Some(history);
+        }
+    }
+
+    /// Appends to the pending console input, recorded in the history
+    pub fn append_input(&mut self, mut xs: Vec<u8>) {
+        let pending = &self.console.reader.get_ref()[self.console.reader.position() as usize..];
+        let mut buf = pending.to_vec();
+        buf.append(&mut xs);
+        self.load_input(buf);
+    }
+
+    fn record_edit(&mut self) {
+        if let Some(mut history) = self.history.take() {
+            history.record_edit(self);
+            self.history = Some(history);
+        }
+    }
+
+    #[inline(always)]
+    fn fetch(&mut self) -> Result<Instruction, Error> {
+        match self.decoded.get(self.pc as usize) {
+            Some(Some(ins)) => Ok(*ins),
+            _ => self.decode_at_pc(),
+        }
+    }
+
+    // decodes the instruction at the program counter, cached if the program
+    // counter can reach the cell again
+    #[cold]
+    fn decode_at_pc(&mut self) -> Result<Instruction, Error> {
+        let pc = self.pc as usize;
+        match self.decoded.get(pc) {
+            Some(Some(ins)) => Ok(*ins),
+            Some(None) => {
+                let ins = decode(self.memory[pc])?;
+                self.decoded[pc] = Some(ins);
+                Ok(ins)
+            }
+            None => decode(self.memory[pc]),
+        }
+    }
+
+    // drops the decoded instruction of a written cell
+    fn invalidate(&mut self, cell: usize) {
+        if let Some(ins) = self.decoded.get_mut(cell) {
+            *ins = None;
+        }
+        self.blocks.invalidate(cell);
+    }
+
+    fn invalidate_all(&mut self) {
+        self.decoded.iter_mut().for_each(|ins| *ins = None);
+        self.blocks.clear();
+    }
+
+    fn execute_traced(&mut self, ins: &Instruction) -> Result<StepResult, Error> {
+        if let Some(mut history) = self.history.take() {
+            history.before_step(self);
+            self.history = Some(history);
+        }
+        if let Some(mut waveform) = self.waveform.take() {
+            waveform.record(self.signals());
+            self.waveform = Some(waveform);
+        }
+        let (pc, insn) = (self.pc, self.memory[self.pc as usize]);
+        let cycle = self.cycles;
+        self.cycles += 1;
+        // a faulting instruction is recorded too
+        let step = self.execute(ins);
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.record(cycle, pc, insn, &self.state());
+            self.tracer = Some(tracer);
+        }
+        step
+    }
+
+    fn execute(&mut self, ins: &Instruction) -> Result<StepResult, Error> {
+        self.idle = false;
+        let (pc, t, n) = (self.pc, self.st0, self.d.peek());
+        self.exec(ins)?;
+        let mut step = StepResult { instruction: *ins, pc, next_pc: self.pc, read: None, write: None };
+        if let ALU(alu) = ins {
+            if alu.opcode == OpCode::OpAtT {
+                step.read = Some(match self.timing {
+                    Timing::Functional => self.access(t, self.st0),
+                    Timing::Verilog if t & IO_MASK != 0 => Access::Io { addr: t, value: self.st0 },
+                    Timing::Verilog => Access::Memory { addr: t, value: self.st0 },
+                });
+            }
+            if alu.n2_at_t {
+                step.write = match self.timing {
+                    Timing::Functional => Some(self.access(t, n)),
+                    Timing::Verilog if t & IO_MASK != 0 => Some(Access::Io { addr: t, value: n }),
+                    Timing::Verilog if self.st0 & IO_MASK == 0 => Some(Access::Memory { addr: self.st0, value: n }),
+                    Timing::Verilog => None,
+                };
+            }
+        }
+        Ok(step)
+    }
+
+    // executes `ins` at the program counter, the step results are left to
+    // `execute` so the fast loops do not build them
+    #[inline(always)]
+    fn exec(&mut self, ins: &Instruction) -> Result<Effect, Error> {
+        let pc = self.pc;
+        let effect = match self.timing {
+            Timing::Functional => {
+                let (mut next, mut t) = (pc, self.st0);
+                let effect = self.exec_functional(ins, &mut next, &mut t);
+                self.pc = next;
+                self.st0 = t;
+                effect?
+            }
+            Timing::Verilog => self.exec_verilog(ins, pc)?,
+        };
+        if self.track_stacks {
+            self.track_moves(ins, pc)?;
+        }
+        Ok(effect)
+    }
+
+    // executes `ins` at `pc` with functional timing, the program counter and
+    // T are passed in so the fast loops can keep them in registers
+    #[inline(always)]
+    fn exec_functional(&mut self, ins: &Instruction, pc: &mut u16, t: &mut u16) -> Result<Effect, Error> {
+        let at = *pc;
+        *pc = (at + 1) & PC_MASK;
         match ins {
             Literal(v) => {
-                self.d.push(self.st0);
-                self.st0 = *v
+                self.d.push_untracked(*t);
+                *t = *v
             }
-            Jump(v) => self.pc = *v,
+            Jump(v) => *pc = *v,
             Call(v) => {
-                self.r.push(self.pc << 1);
-                self.pc = *v
+                self.r.push_untracked(*pc << 1);
+                *pc = *v
             }
             Conditional(v) => {
-                if self.st0 == 0 {
-                    self.pc = *v
+                if *t == 0 {
+                    *pc = *v
                 }
-                self.st0 = self.d.pop()
+                *t = self.d.pop_untracked()
             }
             ALU(alu) => {
                 if alu.r2pc {
-                    self.pc = self.r.peek() >> 1
+                    *pc = (self.r.peek() >> 1) & PC_MASK
                 }
+                let mut effect = Effect::None;
                 if alu.n2_at_t {
-                    self.write_at(self.st0, self.d.peek())?;
+                    effect = self.write_at(*t, self.d.peek())?;
                 }
-                let st0 = self.new_st0(&alu.opcode);
-                self.d.move_sp(alu.d_dir);
-                self.r.move_sp(alu.r_dir);
-                if alu.t2n {
-                    self.d.replace(self.st0)
+                let st0 = match alu.opcode {
+                    OpCode::OpAtT if self.is_memory(*t) => self.memory[(*t >> 1) as usize],
+                    OpCode::OpAtT => {
+                        let value = self.io.read(&mut self.console, *t)?;
+                        self.track_idle(at, *t, value);
+                        effect = Effect::Io;
+                        value
+                    }
+                    _ => self.alu_value(&alu.opcode, *t),
+                };
+                self.move_stacks(alu, *t);
+                *t = st0;
+                return Ok(effect);
+            }
+        }
+        Ok(Effect::None)
+    }
+
+    // executes `ins` at `pc` with j1.v timing
+    fn exec_verilog(&mut self, ins: &Instruction, pc: u16) -> Result<Effect, Error> {
+        self.pc = (pc + 1) & PC_MASK;
+        let effect = match ins {
+            Literal(v) => {
+                self.d.push_untracked(self.st0);
+                self.st0 = *v;
+                Effect::None
+            }
+            Jump(v) => {
+                self.pc = *v;
+                Effect::None
+            }
+            Call(v) => {
+                self.r.push_untracked((pc + 1) << 1);
+                self.pc = *v;
+                Effect::None
+            }
+            Conditional(v) => {
+                if self.st0 == 0 {
+                    self.pc = *v
                 }
-                if alu.t2r {
-                    self.r.replace(self.st0)
+                self.st0 = self.d.pop_untracked();
+                Effect::None
+            }
+            ALU(alu) => {
+                if alu.r2pc {
+                    self.pc = (self.r.peek() >> 1) & PC_MASK
                 }
-                self.st0 = st0
+                return self.exec_verilog_alu(alu, pc);
+            }
+        };
+        self.latch();
+        Ok(effect)
+    }
+
+    // the j1.v ALU: [T] of memory reads the RAM output latched at the last
+    // clock edge, N→[T] strobes io_wr at the old T and writes RAM at the new T
+    fn exec_verilog_alu(&mut self, alu: &AluAttributes, pc: u16) -> Result<Effect, Error> {
+        let (t, n) = (self.st0, self.d.peek());
+        let io = t & IO_MASK != 0;
+        let mut effect = Effect::None;
+        let st0 = match alu.opcode {
+            OpCode::OpAtT if io => {
+                let value = self.io.read(&mut self.console, t)?;
+                self.track_idle(pc, t, value);
+                effect = Effect::Io;
+                value
             }
+            OpCode::OpAtT => self.mem_din,
+            _ => self.alu_value(&alu.opcode, t),
+        };
+        if alu.n2_at_t && io {
+            self.io.write(&mut self.console, t, n)?;
+            self.rx_poll = None;
+            effect = Effect::Io;
         }
-        Ok(())
+        self.move_stacks(alu, t);
+        self.st0 = st0;
+        self.latch();
+        if alu.n2_at_t && st0 & IO_MASK == 0 {
+            let cell = (st0 >> 1) as usize;
+            self.memory[cell] = n;
+            self.invalidate(cell);
+            if effect == Effect::None {
+                effect = Effect::Write(cell);
+            }
+        }
+        Ok(effect)
+    }
+
+    // moves the stack pointers, `t` is T before the instruction
+    fn move_stacks(&mut self, alu: &AluAttributes, t: u16) {
+        self.d.move_sp_untracked(alu.d_dir);
+        self.r.move_sp_untracked(alu.r_dir);
+        if alu.t2n {
+            self.d.replace(t)
+        }
+        if alu.t2r {
+            self.r.replace(t)
+        }
+    }
+
+    // loads the j1.v RAM output register from the cell T addresses
+    fn latch(&mut self) {
+        self.mem_din = self.memory.get((self.st0 >> 1) as usize).copied().unwrap_or(0);
+    }
+
+    // tracks the levels of the stack moves made by the instruction at `pc`
+    // and applies the stack policies to them
+    fn track_moves(&mut self, ins: &Instruction, pc: u16) -> Result<(), Error> {
+        let (d, r) = match ins {
+            Literal(_) => (1, 0),
+            Jump(_) => (0, 0),
+            Call(_) => (0, 1),
+            Conditional(_) => (-1, 0),
+            ALU(alu) => (alu.d_dir, alu.r_dir),
+        };
+        self.d.track(d);
+        self.r.track(r);
+        self.check_stack(StackKind::Data, pc, self.d.level - d as i64)?;
+        self.check_stack(StackKind::Return, pc, self.r.level - r as i64)
+    }
+
+    // levels are tracked while a stack policy or `water_marks` needs them,
+    // they restart from the stack pointers when tracking starts
+    fn update_tracking(&mut self) {
+        let track = self.water_marks || self.data_policy != StackPolicy::Wrap || self.return_policy != StackPolicy::Wrap;
+        if track && !self.track_stacks {
+            self.d.sync_level();
+            self.r.sync_level();
+        }
+        self.track_stacks = track;
+    }
+
+    // applies the stack policy to an overflow or underflow by the instruction
+    // at `pc`, a trap leaves the instruction executed
+    fn check_stack(&mut self, stack: StackKind, pc: u16, before: i64) -> Result<(), Error> {
+        let (fault, policy) = match stack {
+            StackKind::Data => (self.d.fault_since(before), self.data_policy),
+            StackKind::Return => (self.r.fault_since(before), self.return_policy),
+        };
+        match (fault, policy) {
+            (None, _) | (_, StackPolicy::Wrap) => Ok(()),
+            (Some(kind), StackPolicy::Warn) => {
+                let cycle = self.cycles - 1;
+                self.stack_events.push(StackEvent { cycle, pc, stack, kind });
+                Ok(())
+            }
+            (Some(kind), StackPolicy::Trap) => Err(Error::StackFault { pc, stack, kind }),
+        }
+    }
+
+    /// Data stack, `level` and the water marks track overflows and underflows
+    /// while a stack policy other than `Wrap` or `water_marks` asks for them
+    pub fn data_stack(&self) -> &Stack {
+        &self.d
+    }
+
+    pub fn return_stack(&self) -> &Stack {
+        &self.r
+    }
+
+    /// Restarts the water marks of both stacks from their current levels
+    pub fn reset_water_marks(&mut self) {
+        self.d.reset_water_marks();
+        self.r.reset_water_marks();
+    }
+
+    // tx! also reads the ?rx register to check the transmit busy bit, so a
+    // program that polls ?key and emits reads it empty from two places. Forth
+    // is idle when the same instruction reads ?rx empty twice with no i/o
+    // write in between, a full turn of the `begin ?key until` loop of key.
+    fn track_idle(&mut self, pc: u16, addr: u16, value: u16) {
+        if Some(addr) == self.io.console_status() {
+            // bit 0 is set when input is available
+            let empty = value & 1 == 0;
+            self.idle = empty && self.rx_poll == Some(pc);
+            self.rx_poll = if empty { Some(pc) } else { None };
+        }
+    }
+
+    /// True if the last instruction was forth polling ?rx for console
+    /// input that is not there
+    pub fn is_waiting_for_input(&self) -> bool {
+        self.idle
     }
 
     fn reset(&mut self) {
@@ -131,34 +1089,52 @@ impl CPU {
         self.st0 = 0;
         self.d = Stack::default();
         self.r = Stack::default();
+        self.latch();
+    }
+
+    // true if byte address `addr` is a memory cell, false for i/o
+    fn is_memory(&self, addr: u16) -> bool {
+        ((addr >> 1) as usize) < self.memory.len() && (addr & IO_MASK == 0 || !self.io.is_mapped(addr))
     }
 
-    fn write_at(&mut self, addr: u16, value: u16) -> Result<(), String> {
-        if addr & IO_MASK == 0 {
-            self.memory[(addr >> 1) as usize] = value;
+    fn access(&self, addr: u16, value: u16) -> Access {
+        if self.is_memory(addr) {
+            Access::Memory { addr, value }
+        } else {
+            Access::Io { addr, value }
         }
-        match addr {
-            0x7000 => self.console.write_char(value as u8),  // key
-            0x7002 => return Err("bye".to_string()),         // bye
-            _ => ()
+    }
+
+    fn write_at(&mut self, addr: u16, value: u16) -> Result<Effect, Error> {
+        if self.is_memory(addr) {
+            let cell = (addr >> 1) as usize;
+            self.memory[cell] = value;
+            self.invalidate(cell);
+            return Ok(Effect::Write(cell));
         }
-        Ok(())
+        self.io.write(&mut self.console, addr, value)?;
+        self.rx_poll = None;
+        Ok(Effect::Io)
     }
 
-    fn read_at(&mut self, addr: u16) -> u16 {
-        if addr & IO_MASK == 0 {
-            return self.memory[(addr >> 1) as usize];
+    fn read_at(&mut self, addr: u16) -> Result<u16, Error> {
+        if self.is_memory(addr) {
+            return Ok(self.memory[(addr >> 1) as usize]);
         }
-        match addr {
-            0x7000 => self.console.read_char() as u16,  // tx!
-            0x7001 => 1,                                // ?rx returns 1 or 0
-            _ => 0 as u16 // error
+        self.io.read(&mut self.console, addr)
+    }
+
+    fn new_st0(&mut self, opcode: &OpCode) -> Result<u16, Error> {
+        match opcode {
+            OpCode::OpAtT => self.read_at(self.st0),                    // [T]
+            _ => Ok(self.alu_value(opcode, self.st0)),
         }
     }
 
-    fn new_st0(&mut self, opcode: &OpCode) -> u16 {
+    // the ALU result of every opcode but [T], which can fail on i/o
+    #[inline(always)]
+    fn alu_value(&self, opcode: &OpCode, t: u16) -> u16 {
         let bool_value = |b: bool| -> u16 { if b { !0 } else { 0 } };
-        let t = self.st0;       // T
         let n = self.d.peek();  // N
         let r = self.r.peek();  // R
         match opcode {
@@ -174,65 +1150,263 @@ impl CPU {
             OpCode::OpNrshiftT => n >> (t & 0xf),                       // N >> T
             OpCode::OpTminus1 => t.wrapping_sub(1),                     // T - 1
             OpCode::OpR => r,                                           // R
-            OpCode::OpAtT => self.read_at(t),                           // [T]
+            OpCode::OpAtT => self.mem_din,                              // [T], read by the callers
             OpCode::OpNlshiftT => n << (t & 0xf),                       // N << T
             OpCode::OpDepth => (self.r.depth() << 8) | self.d.depth(),  // depth (dsp)
             OpCode::OpNuleT => bool_value(n < t),                       // Nu < T
         }
     }
 
-    pub fn load_bytes(&mut self, data: &Vec<u8>) -> std::io::Result<()> {
-        if data.len() % 2 != 0 {
-            return Err(Error::new(ErrorKind::Other, "Odd number of bytes provided"));
+    /// Copy of the complete machine state, see `Snapshot`
+    pub fn snapshot(&self) -> Snapshot {
+        let (mut d, mut r) = (self.d, self.r);
+        if !self.track_stacks {
+            d.sync_level();
+            r.sync_level();
+        }
+        Snapshot {
+            memory: self.memory.to_vec(),
+            pc: self.pc,
+            st0: self.st0,
+            d,
+            r,
+            cycles: self.cycles,
+            console: ConsoleState::new(&self.console),
+        }
+    }
+
+    /// Replaces the machine state with a snapshot, fails if the snapshot
+    /// memory size is not the CPU memory size
+    pub fn restore(&mut self, snapshot: &Snapshot) -> std::io::Result<()> {
+        if snapshot.memory.len() != self.memory.len() {
+            return Err(std::io::Error::other(format!(
+                "Snapshot memory size {} does not match cpu memory size {}",
+                snapshot.memory.len(), self.memory.len())));
+        }
+        self.memory.copy_from_slice(&snapshot.memory);
+        self.invalidate_all();
+        self.pc = snapshot.pc;
+        self.st0 = snapshot.st0;
+        self.d = snapshot.d;
+        self.r = snapshot.r;
+        self.latch();
+        self.cycles = snapshot.cycles;
+        self.console = snapshot.console.to_console();
+        self.idle = false;
+        self.rx_poll = None;
+        Ok(())
+    }
+
+    pub fn load_bytes(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if !data.len().is_multiple_of(2) {
+            return Err(std::io::Error::other("Odd number of bytes provided"));
         }
 
         let size = data.len() >> 1;
-        let _len = self.memory.len();
-        if size >= self.memory.len() {
-            return Err(Error::new(ErrorKind::Other, "Binary too big for cpu memory to load"));
+        if size > self.memory.len() {
+            return Err(std::io::Error::other(format!(
+                "Binary of 0x{:X} cells too big for cpu memory of 0x{:X} cells", size, self.memory.len())));
         }
 
-        let mut current = &data[..];
+        let mut current = data;
         let mut i = 0;
-        while current.len() > 0 {
+        while !current.is_empty() {
             self.memory[i] = current.read_u16::<LittleEndian>()?;
             i += 1;
         }
+        self.invalidate_all();
+        self.latch();
+        Ok(())
+    }
+
+    /// Map file
+    ///
+    /// Maps a host file into upper memory like the reference j1.c simulator:
+    /// the size in bytes is stored at cell 0x2000 (byte address 0x4000) and
+    /// the contents follow from cell 0x2001, two bytes per cell little endian.
+    /// eForth `cold` evaluates this text before reading the console. Needs a
+    /// memory size above 0x2000 cells and fails if the file does not fit.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use j1::cpu::CPU;
+    /// use j1::j1e_bin;
+    ///
+    /// let mut cpu = CPU::builder().memory_size(0x4000).build().unwrap();
+    /// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+    /// cpu.map_file(b": sq dup * ;").unwrap();
+    ///
+    /// cpu.run(b"7 sq .\n".to_vec()).unwrap();
+    /// assert!(cpu.console.get_log().ends_with(" 31 ok\n"));
+    /// ```
+    pub fn map_file(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if self.memory.len() <= MAP_FILE_CELL {
+            return Err(std::io::Error::other(format!(
+                "Mapping a file needs more than 0x{:X} cells of cpu memory", MAP_FILE_CELL)));
+        }
+        let capacity = (self.memory.len() - MAP_FILE_CELL - 1) << 1;
+        if data.len() > capacity {
+            return Err(std::io::Error::other(format!(
+                "File of 0x{:X} bytes too big for 0x{:X} bytes of cpu memory above 0x{:X} cells",
+                data.len(), capacity, MAP_FILE_CELL)));
+        }
+
+        self.memory[MAP_FILE_CELL] = data.len() as u16;
+        for (i, xs) in data.chunks(2).enumerate() {
+            let hi = xs.get(1).copied().unwrap_or(0);
+            self.memory[MAP_FILE_CELL + 1 + i] = u16::from_le_bytes([xs[0], hi]);
+        }
         Ok(())
     }
 
-    pub fn dump_asm(&self, addr_start: u16, addr_end: u16) -> Vec<String> {
+    /// Byte addresses `addr_start` through `addr_end` are inside memory
+    pub fn check_range(&self, addr_start: u16, addr_end: u16) -> std::io::Result<()> {
+        if addr_start > addr_end || (addr_end >> 1) as usize >= self.memory.len() {
+            return Err(std::io::Error::other(format!(
+                "Invalid address range 0x{:04X} to 0x{:04X} for memory of 0x{:X} bytes",
+                addr_start, addr_end, self.memory.len() << 1)));
+        }
+        Ok(())
+    }
+
+    pub fn dump_asm(&self, addr_start: u16, addr_end: u16) -> std::io::Result<Vec<String>> {
+        self.dump_asm_as(addr_start, addr_end, Notation::Fields)
+    }
+
+    /// `dump_asm` with instructions in `notation`
+    pub fn dump_asm_as(&self, addr_start: u16, addr_end: u16, notation: Notation) -> std::io::Result<Vec<String>> {
+        self.check_range(addr_start, addr_end)?;
         let mut xs = Vec::new();
         xs.push("Address,Value,Instruction".to_string());
-        for addr in (addr_start..addr_end + 2).step_by(2) {
+        for addr in (addr_start..=addr_end).step_by(2) {
             let v = self.memory[(addr >> 1) as usize];
             let asm = decode(v).unwrap();
-            xs.push(format!("0x{:04X},0x{:04X},{}", addr, v, asm.show()));
+            xs.push(format!("0x{:04X},0x{:04X},{}", addr, v, csv_field(&asm.show_as(notation))));
         }
-        xs
+        Ok(xs)
     }
 
-    pub fn dump_ast(&self, addr_start: u16, addr_end: u16) -> Vec<String> {
+    /// Loads the rows of a `dump_asm` CSV, assembling the Instruction column
+    /// at each Address. The Value column is kept where it decodes to the same
+    /// instruction, so ALU bit 4, which the text leaves out, survives.
+    pub fn load_asm(&mut self, csv: &str) -> std::io::Result<()> {
+        for (n, line) in csv.lines().enumerate().skip(1) {
+            let err = |message: String| std::io::Error::other(format!("line {}: {}", n + 1, message));
+            let fields: Vec<&str> = line.splitn(3, ',').collect();
+            if fields.len() != 3 {
+                return Err(err(format!("Expected Address,Value,Instruction: {}", line)));
+            }
+            let addr = u16::from_str_radix(fields[0].trim_start_matches("0x"), 16)
+                .map_err(|_e| err(format!("Invalid address: {}", fields[0])))?;
+            let asm: Instruction = fields[2].parse().map_err(|e: Error| err(e.to_string()))?;
+            self.check_range(addr, addr).map_err(|e| err(e.to_string()))?;
+            let value = u16::from_str_radix(fields[1].trim_start_matches("0x"), 16).ok();
+            self.memory[(addr >> 1) as usize] = match value {
+                Some(v) if decode(v).ok() == Some(asm) => v,
+                _ => asm.encode(),
+            };
+        }
+        self.invalidate_all();
+        self.latch();
+        self.record_edit();
+        Ok(())
+    }
+
+    pub fn dump_ast(&self, addr_start: u16, addr_end: u16) -> std::io::Result<Vec<String>> {
+        self.check_range(addr_start, addr_end)?;
         let mut xs = Vec::new();
         xs.push("Address,Value,Instruction".to_string());
-        for addr in (addr_start..addr_end + 2).step_by(2) {
+        for addr in (addr_start..=addr_end).step_by(2) {
             let v = self.memory[(addr >> 1) as usize];
             let asm = decode(v).unwrap();
             xs.push(format!("0x{:04X},0x{:04X},{}", addr, v, asm));
         }
-        xs
+        Ok(xs)
+    }
+
+    /// `dump_asm` with the eForth dictionary: the word whose code starts at an
+    /// address is its label, and a CALL, UBRANCH or 0BRANCH target is
+    /// commented with the word at or inside it, `name+offset` in hex bytes.
+    /// Cells that are not code, see `disasm::classify`, are shown as
+    /// `.link`, `.name`, `.str` or `.data` with a string or name on its first
+    /// cell and the rest of its cells left blank. Code is in `notation`.
+    pub fn dump_symbols(&self, addr_start: u16, addr_end: u16, notation: Notation) -> std::io::Result<Vec<String>> {
+        self.check_range(addr_start, addr_end)?;
+        let dictionary = Dictionary::from_memory(&self.memory);
+        let kinds = classify(&self.memory, &dictionary);
+        let mut xs = Vec::new();
+        xs.push("Address,Value,Instruction,Label,Comment".to_string());
+        for addr in (addr_start..=addr_end).step_by(2) {
+            let i = (addr >> 1) as usize;
+            let v = self.memory[i];
+            let label = dictionary.at(addr).map_or("", |w| &w.name);
+            let (text, comment) = match kinds[i] {
+                CellKind::Code => (decode(v).unwrap().show_as(notation), symbol(&dictionary, decode(v).unwrap())),
+                CellKind::Header => match dictionary.words().iter().find(|w| w.name_addr == addr || w.name_addr == addr + 2) {
+                    Some(w) if w.name_addr == addr + 2 => {
+                        let previous = dictionary.words().iter().find(|x| x.name_addr == v);
+                        (format!(".link {:04X}", v), previous.map_or(String::new(), |x| x.name.clone()))
+                    }
+                    Some(w) => {
+                        let flags = [(w.immediate, "immediate"), (w.compile_only, "compile-only")];
+                        let flags: Vec<&str> = flags.iter().filter(|x| x.0).map(|x| x.1).collect();
+                        (format!(".name {}", quoted(w.name.chars())), flags.join(" "))
+                    }
+                    None => (String::new(), String::new()),
+                },
+                CellKind::String if i == 0 || kinds[i - 1] != CellKind::String => {
+                    (format!(".str {}", counted(&self.memory, addr)), String::new())
+                }
+                CellKind::String => (String::new(), String::new()),
+                CellKind::Data => (format!(".data {:04X}", v), String::new()),
+            };
+            xs.push(format!("0x{:04X},0x{:04X},{},{},{}", addr, v, csv_field(&text), csv_field(label), csv_field(&comment)));
+        }
+        Ok(xs)
+    }
+}
+
+// word at or inside the target of a CALL, UBRANCH or 0BRANCH
+fn symbol(dictionary: &Dictionary, asm: Instruction) -> String {
+    match asm {
+        Jump(target) | Conditional(target) | Call(target) => {
+            let target = target << 1;
+            match dictionary.within(target) {
+                Some(w) if w.code == target => w.name.clone(),
+                Some(w) => format!("{}+{:X}", w.name, target - w.code),
+                None => String::new(),
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+// quotes a csv field holding a comma or quote, forth names like `,` and `."`
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
-
-    use crate::cpu::CPU;
-    use crate::instruction::{Instruction, OpCode, AluAttributes};
+    use std::time::Duration;
+
+    use crate::breakpoint::{Breakpoint, InstructionPattern};
+    use crate::cpu::{Access, CpuState, StopReason, CPU};
+    use crate::error::{Budget, Error};
+    use crate::stack::{StackEvent, StackFaultKind, StackKind, StackPolicy};
+    use crate::history::History;
+    use crate::snapshot::Snapshot;
+    use crate::instruction::{Instruction, Notation, OpCode, AluAttributes};
     use crate::instruction::Instruction::{ALU, Call, Conditional, Jump, Literal};
     use crate::instruction::OpCode::*;
     use crate::utils::read_binary;
+    use crate::verilog::{Bus, Timing};
     use crate::j1e_bin;
 
     fn load_binary() -> CPU {
@@ -242,7 +1416,7 @@ mod tests {
         let full_file_name = p.display().to_string();
 
         let mut cpu = CPU::new();
-        cpu.load_bytes(&mut read_binary(&full_file_name).unwrap()).unwrap();
+        cpu.load_bytes(&read_binary(&full_file_name).unwrap()).unwrap();
         cpu
     }
 
@@ -250,8 +1424,8 @@ mod tests {
     #[test]
     fn dump_asm() {
         let mut cpu = CPU::new();
-        cpu.load_bytes(&j1e_bin::J1E_BIN.to_vec()).unwrap();
-        let xs = cpu.dump_asm(0x00C2, 0x00C4);
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        let xs = cpu.dump_asm(0x00C2, 0x00C4).unwrap();
         // for x in xs.clone().iter() {
         //     println!("{}", x)
         // }
@@ -263,11 +1437,47 @@ mod tests {
         assert_eq!(expected, xs);
     }
 
+    #[test]
+    fn load_asm() {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        let csv = cpu.dump_asm(0x0000, 0x3FFE).unwrap().join("\n");
+
+        let mut other = CPU::new();
+        other.load_asm(&csv).unwrap();
+        assert_eq!(cpu.memory(), other.memory());
+        other.run(b"2 3 * .\n".to_vec()).unwrap();
+        assert!(other.console.get_log().ends_with(" 6 ok\n"));
+
+        // the Value column only where it is the same instruction, with bit 4
+        other.load_asm("Address,Value,Instruction\n0x0000,0x6013,ALU     T d-1\n0x0002,0x1234,LIT     0012").unwrap();
+        assert_eq!([0x6013, 0x8012], other.memory()[..2]);
+
+        let test_cases = [
+            "Address,Value,Instruction\n0x0000,0x0000",
+            "Address,Value,Instruction\nzz,0x0000,LIT     0012",
+            "Address,Value,Instruction\n0x4000,0x8012,LIT     0012",
+            "Address,Value,Instruction\n0x0002,0x8012,LIT     8012",
+        ];
+        for csv in test_cases.iter() {
+            assert!(other.load_asm(csv).is_err(), "{}", csv);
+        }
+
+        // a load is an edit the history keeps across replays
+        other.history = Some(History::new(100));
+        other.run(b"1 .\n".to_vec()).unwrap();
+        let now = other.cycles();
+        other.load_asm("Address,Value,Instruction\n0x3FFE,0x8012,LIT     0012").unwrap();
+        other.goto_cycle(now - 10).unwrap();
+        other.goto_cycle(now).unwrap();
+        assert_eq!(0x8012, other.memory()[0x1FFF]);
+    }
+
     #[test]
     fn dump_ast() {
         let mut cpu = CPU::new();
-        cpu.load_bytes(&j1e_bin::J1E_BIN.to_vec()).unwrap();
-        let xs = cpu.dump_ast(0x00C2, 0x00C4);
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        let xs = cpu.dump_ast(0x00C2, 0x00C4).unwrap();
         // for x in xs.clone().iter() {
         //     println!("{}", x)
         // }
@@ -279,11 +1489,238 @@ mod tests {
         assert_eq!(expected, xs);
     }
 
+    #[test]
+    fn dump_symbols() {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        let test_cases = [
+            (0x19D4, 0x19DA, vec![
+                "Address,Value,Instruction,Label,Comment",
+                "0x19D4,0x8002,LIT     0002,cold,",
+                "0x19D6,0xBE80,LIT     3E80,,",
+                "0x19D8,0x8042,LIT     0042,,",
+                "0x19DA,0x4566,CALL    0ACC,,cmove",
+            ]),
+            // names with a comma or quote are quoted
+            (0x0A6E, 0x0A6E, vec!["Address,Value,Instruction,Label,Comment", "0x0A6E,0x4520,CALL    0A40,\".\"\"|\",do$"]),
+            // headers, the string after `."|` and a CREATE body
+            (0x07CE, 0x07D2, vec![
+                "Address,Value,Instruction,Label,Comment",
+                "0x07CE,0x0279,UBRANCH 04F2,,2drop",
+                "0x07D0,0x07AA,.link 07AA,,fill",
+                "0x07D2,0x6505,\".name \"\"erase\"\"\",,",
+            ]),
+            (0x0E84, 0x0E8C, vec![
+                "Address,Value,Instruction,Label,Comment",
+                "0x0E84,0x2746,0BRANCH 0E8C,,.ok+10",
+                "0x0E86,0x4537,CALL    0A6E,,\".\"\"|\"",
+                "0x0E88,0x2003,\".str \"\" ok\"\"\",,",
+                "0x0E8A,0x6B6F,,,",
+                "0x0E8C,0x0519,UBRANCH 0A32,,cr",
+            ]),
+            (0x03A0, 0x03A2, vec!["Address,Value,Instruction,Label,Comment", "0x03A0,0x41CB,CALL    0396,up,dovar", "0x03A2,0x3E80,.data 3E80,,"]),
+            (0x0A56, 0x0A56, vec!["Address,Value,Instruction,Label,Comment", "0x0A56,0x2443,\".name \"\"$\\\"\"|\"\"\",,compile-only"]),
+            // the pick chain is not in the dictionary
+            (0x00C4, 0x00C4, vec!["Address,Value,Instruction,Label,Comment", "0x00C4,0x404E,CALL    009C,,"]),
+        ];
+        for (start, end, expected) in test_cases.iter() {
+            assert_eq!(*expected, cpu.dump_symbols(*start, *end, Notation::Fields).unwrap());
+        }
+        assert!(cpu.dump_symbols(0x0004, 0x0002, Notation::Fields).is_err());
+    }
+
+    #[test]
+    fn memory_size() {
+        let test_cases = [(0x1000, false), (0x2000, true), (0x4000, true), (0x8000, true), (0x8001, false)];
+        for (size, ok) in test_cases.iter() {
+            assert_eq!(*ok, CPU::builder().memory_size(*size).build().is_ok());
+        }
+
+        let mut cpu = CPU::new();
+        assert!(cpu.load_bytes(&vec![0u8; 0x4000]).is_ok());
+        assert!(cpu.load_bytes(&vec![0u8; 0x4002]).is_err());
+        assert!(cpu.dump_asm(0x3FFE, 0x3FFE).is_ok());
+        assert!(cpu.dump_asm(0x3FFE, 0x4000).is_err());
+        assert!(cpu.dump_asm(0x0004, 0x0002).is_err());
+
+        // 0x4000.. is i/o with 8K words and memory with 16K words
+        for (size, access) in [(0x2000, Access::Io { addr: 0x4000, value: 0 }), (0x4000, Access::Memory { addr: 0x4000, value: 0 })].iter() {
+            let mut cpu = CPU::builder().memory_size(*size).build().unwrap();
+            // LIT 4000, ALU [T]
+            cpu.load_bytes(&[0x00, 0xC0, 0x00, 0x6C]).unwrap();
+            let xs = cpu.step_n(2).unwrap();
+            assert_eq!(Some(*access), xs[1].read);
+        }
+        let cpu = CPU::builder().memory_size(0x4000).build().unwrap();
+        assert!(cpu.dump_asm(0x4000, 0x7FFE).is_ok());
+    }
+
+    #[test]
+    fn stack_policy() {
+        // eForth itself stays inside both stacks
+        let mut cpu = CPU::builder()
+            .stop_on_idle(true)
+            .stack_policy(StackKind::Data, StackPolicy::Trap)
+            .stack_policy(StackKind::Return, StackPolicy::Trap)
+            .build().unwrap();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        cpu.run(b": sq dup * ; 7 sq . 1 2 3 .s\n".to_vec()).unwrap();
+        let d = cpu.data_stack();
+        assert!(d.low_water >= 0 && d.high_water < 32);
+
+        // NOOP, then ALU T d-1 twice underflows
+        let program = [0x00, 0x60, 0x03, 0x60, 0x03, 0x60, 0x00, 0x80];
+        let underflow = Error::StackFault { pc: 1, stack: StackKind::Data, kind: StackFaultKind::Underflow };
+        // Wrap only tracks the levels with water marks
+        let test_cases = [
+            (StackPolicy::Wrap, false, None, 0, 0),
+            (StackPolicy::Wrap, true, None, 0, -2),
+            (StackPolicy::Warn, false, None, 2, -2),
+            (StackPolicy::Trap, false, Some(underflow), 0, -1),
+        ];
+        for (policy, water_marks, error, events, low_water) in test_cases.iter() {
+            let mut cpu = CPU::builder().stack_policy(StackKind::Data, *policy).water_marks(*water_marks).build().unwrap();
+            cpu.load_bytes(&program).unwrap();
+            assert_eq!(*error, cpu.step_n(3).err());
+            assert_eq!(*events, cpu.stack_events.len());
+            assert_eq!(*low_water, cpu.data_stack().low_water);
+        }
+
+        // levels restart from the stack pointers when tracking starts
+        let mut cpu = CPU::builder().stop_on_idle(true).build().unwrap();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        cpu.run(b"1 2 3\n".to_vec()).unwrap();
+        cpu.data_policy = StackPolicy::Trap;
+        cpu.run(b"drop drop .\n".to_vec()).unwrap();
+        assert_eq!(cpu.data_stack().sp as i64, cpu.data_stack().level);
+
+        let mut cpu = CPU::builder().stack_policy(StackKind::Data, StackPolicy::Warn).build().unwrap();
+        cpu.load_bytes(&program).unwrap();
+        cpu.step_n(3).unwrap();
+        assert_eq!(vec![(1, 1), (2, 2)], cpu.stack_events.iter().map(|e| (e.cycle, e.pc)).collect::<Vec<_>>());
+
+        // LIT 0 from level -2 to 32, the last push wraps sp past 31 entries
+        cpu.reset_water_marks();
+        for _ in 0..34 {
+            cpu.set_pc(3);
+            cpu.step().unwrap();
+        }
+        assert_eq!((32, -2), (cpu.data_stack().high_water, cpu.data_stack().low_water));
+        assert_eq!(StackEvent { cycle: 36, pc: 3, stack: StackKind::Data, kind: StackFaultKind::Overflow }, cpu.stack_events[2]);
+        assert_eq!(3, cpu.stack_events.len());
+
+        // LIT 0, UBRANCH 0000: 40000 net pushes stay an overflow
+        let test_cases = [(StackPolicy::Warn, false, 40_000 - 31), (StackPolicy::Wrap, true, 0)];
+        for (policy, water_marks, events) in test_cases.iter() {
+            let mut cpu = CPU::builder().stack_policy(StackKind::Data, *policy).water_marks(*water_marks).build().unwrap();
+            cpu.load_bytes(&[0x00, 0x80, 0x00, 0x00]).unwrap();
+            cpu.limits.per_call = Some(80_000);
+            assert!(cpu.run(Vec::new()).is_err());
+            let d = cpu.data_stack();
+            assert_eq!((40_000, 40_000, 0), (d.level, d.high_water, d.low_water));
+            assert_eq!(*events, cpu.stack_events.len());
+            assert!(cpu.stack_events.iter().all(|e| e.kind == StackFaultKind::Overflow));
+            let mut buf = Vec::new();
+            cpu.snapshot().write(&mut buf).unwrap();
+            assert_eq!(cpu.snapshot(), Snapshot::read(&mut &buf[..]).unwrap());
+        }
+    }
+
+    #[test]
+    fn decoded_cache() {
+        let mut cpu = CPU::new();
+        // LIT 6000, LIT 0008, N→[T] d-1, UBRANCH 0008, LIT 0001
+        cpu.load_bytes(&[0x00, 0xE0, 0x08, 0x80, 0x23, 0x60, 0x04, 0x00, 0x01, 0x80]).unwrap();
+        cpu.set_pc(4);
+        cpu.step().unwrap();
+        assert_eq!(1, cpu.state().t);
+
+        // the program overwrites cell 4 with a NOOP before it runs it again
+        cpu.set_pc(0);
+        let xs = cpu.step_n(5).unwrap();
+        assert_eq!(Instruction::ALU(AluAttributes::default()), xs[4].instruction);
+        assert_eq!(8, cpu.state().t);
+
+        let test_cases: [(u16, Instruction); 2] = [(0x8001, Literal(1)), (0x0004, Jump(4))];
+        for (v, expected) in test_cases.iter() {
+            cpu.write_memory(4, *v);
+            cpu.set_pc(4);
+            assert_eq!(*expected, cpu.step().unwrap().instruction);
+        }
+        cpu.load_bytes(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, 0x80]).unwrap();
+        cpu.set_pc(4);
+        assert_eq!(Literal(7), cpu.step().unwrap().instruction);
+        cpu.restore(&CPU::new().snapshot()).unwrap();
+        assert_eq!(Jump(0), cpu.step().unwrap().instruction);
+    }
+
+    #[test]
+    fn verilog_timing() {
+        let script = b"decimal\n: fib dup 2 < if drop 1 else dup 2 - recurse swap 1 - recurse + then ;\n10 fib . 1 2 3 .s\n";
+        let mut runs = Vec::new();
+        for timing in [Timing::Functional, Timing::Verilog].iter() {
+            let mut cpu = CPU::builder().timing(*timing).stop_on_idle(true).build().unwrap();
+            cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+            assert_eq!(Ok(StopReason::WaitingForInput), cpu.run(script.to_vec()));
+            runs.push((cpu.console.get_log(), cpu.cycles()));
+        }
+        assert_eq!(runs[0], runs[1]);
+
+        let test_cases = [
+            // LIT 0100, LIT 0102, N N→[T] d-1: RAM is written at the new T
+            (vec![0x00, 0x81, 0x02, 0x81, 0x23, 0x61], 3, (0x0100, 0x0000), (0x0100, 0x0100)),
+            // LIT 0001, T→R r+1, depth
+            (vec![0x01, 0x80, 0x44, 0x60, 0x00, 0x6E], 3, (0x0101, 0), (0x0101, 0)),
+            // LIT 4000, [T]: 0x4000 is RAM with 16K words, unmapped i/o on j1.v
+            (vec![0x00, 0xC0, 0x00, 0x6C], 2, (0x1234, 0), (0x0000, 0)),
+        ];
+        for (program, n, functional, verilog) in test_cases.iter() {
+            for (timing, (t, cell)) in [(Timing::Functional, functional), (Timing::Verilog, verilog)].iter() {
+                let mut cpu = CPU::builder().timing(*timing).memory_size(0x4000).build().unwrap();
+                cpu.load_bytes(program).unwrap();
+                cpu.write_memory(0x2000, 0x1234);
+                cpu.step_n(*n).unwrap();
+                assert_eq!((*t, *cell), (cpu.state().t, cpu.memory()[0x80]), "{:?} {:02X?}", timing, program);
+            }
+        }
+
+        // strobes fire for memory addresses too
+        let mut cpu = CPU::builder().timing(Timing::Verilog).build().unwrap();
+        // LIT 0005, LIT 0010, N→[T] d-1, [T]
+        cpu.load_bytes(&[0x05, 0x80, 0x10, 0x80, 0x23, 0x60, 0x00, 0x6C]).unwrap();
+        cpu.step_n(2).unwrap();
+        assert_eq!(Bus { io_rd: false, io_wr: true, io_addr: 0x10, io_dout: 5 }, cpu.bus());
+        cpu.step().unwrap();
+        assert_eq!(Bus { io_rd: true, io_wr: false, io_addr: 0x10, io_dout: 0 }, cpu.bus());
+        assert_eq!(Some(Access::Memory { addr: 0x10, value: 0 }), cpu.step().unwrap().read);
+    }
+
+    #[test]
+    fn map_file() {
+        let mut cpu = CPU::builder().memory_size(0x4000).build().unwrap();
+        cpu.map_file(b"abc").unwrap();
+        assert_eq!([3, 0x6261, 0x0063], cpu.memory[0x2000..0x2003]);
+
+        let test_cases = [(0x2000, 0, false), (0x4000, 0x3FFE, true), (0x4000, 0x3FFF, false), (0x8000, 0xBFFE, true)];
+        for (size, len, ok) in test_cases.iter() {
+            let mut cpu = CPU::builder().memory_size(*size).build().unwrap();
+            assert_eq!(*ok, cpu.map_file(&vec![b' '; *len]).is_ok());
+        }
+
+        // eForth cold evaluates the mapped text
+        let mut cpu = CPU::builder().memory_size(0x4000).build().unwrap();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        cpu.stop_on_idle = true;
+        cpu.map_file(b"variable x 5 x !\n: x+ x @ + ;").unwrap();
+        cpu.run(b"3 x+ .\n".to_vec()).unwrap();
+        assert!(cpu.console.get_log().ends_with(" 8 ok\n"));
+    }
+
 
     #[test]
     fn run() {
         let mut cpu = CPU::new();
-        cpu.load_bytes(&j1e_bin::J1E_BIN.to_vec()).unwrap();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
 
         cpu.run(b"2 3 * .\n".to_vec()).unwrap();
         let s = cpu.console.get_log();
@@ -296,6 +1733,147 @@ mod tests {
         assert!(s.ends_with(" 1 2 3 4 5<tos ok\n"));
     }
 
+    #[test]
+    fn step() {
+        let mut cpu = CPU::new();
+        // LIT 0005, LIT 0010, ALU T N→[T], ALU [T], CALL 0010
+        cpu.load_bytes(&[0x05, 0x80, 0x10, 0x80, 0x20, 0x60, 0x00, 0x6c, 0x08, 0x40]).unwrap();
+
+        let s = cpu.step().unwrap();
+        assert_eq!((Literal(5), 0, 1, None, None), (s.instruction, s.pc, s.next_pc, s.read, s.write));
+
+        let xs = cpu.step_n(3).unwrap();
+        assert_eq!(3, xs.len());
+        assert_eq!(Some(Access::Memory { addr: 0x10, value: 5 }), xs[1].write);
+        assert_eq!(Some(Access::Memory { addr: 0x10, value: 5 }), xs[2].read);
+        assert_eq!(5, cpu.memory[8]);
+
+        let s = cpu.step().unwrap();
+        assert_eq!((Call(8), 4, 8), (s.instruction, s.pc, s.next_pc));
+
+        // io access
+        cpu.console.load(&mut b"A".to_vec());
+        let alu = AluAttributes { opcode: OpAtT, ..AluAttributes::default() };
+        cpu.st0 = 0x7000;
+        let s = cpu.execute(&ALU(alu)).unwrap();
+        assert_eq!(Some(Access::Io { addr: 0x7000, value: 'A' as u16 }), s.read);
+    }
+
+    #[test]
+    fn state() {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&[0x01, 0x80, 0x02, 0x80, 0x03, 0x40]).unwrap();
+        let before = cpu.state();
+        assert_eq!(CpuState::default(), before);
+
+        cpu.step_n(3).unwrap();
+        let after = cpu.state();
+        let expected = CpuState {
+            pc: 3,
+            t: 2,
+            data_stack: vec![0, 1],
+            return_stack: vec![6],
+            data_depth: 2,
+            return_depth: 1,
+        };
+        assert_eq!(expected, after);
+        assert_eq!(after, cpu.clone().state());
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn run_breakpoints() {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        cpu.run(b"variable x\n".to_vec()).unwrap();
+
+        // `!` => ALU T N→[T] d-1
+        let store = cpu.memory.iter().position(|v| *v == 0x6023).unwrap() as u16;
+        let id = cpu.breakpoints.add(Breakpoint::Pc(store));
+        let reason = cpu.run(b"5 x !\n".to_vec()).unwrap();
+        match reason {
+            StopReason::Breakpoint(hit) => assert_eq!((id, store), (hit.id, hit.pc)),
+            _ => panic!("expected breakpoint, got {:?}", reason),
+        }
+        assert_eq!(store, cpu.pc);
+
+        // resume steps over the breakpoint at pc
+        cpu.breakpoints.clear();
+        let x = cpu.state().t;
+        let id = cpu.breakpoints.add(Breakpoint::Write(x));
+        let reason = cpu.resume().unwrap();
+        match reason {
+            StopReason::Breakpoint(hit) => {
+                assert_eq!(id, hit.id);
+                assert_eq!(Some(Access::Memory { addr: x, value: 5 }), hit.access);
+            }
+            _ => panic!("expected watchpoint, got {:?}", reason),
+        }
+
+        cpu.breakpoints.clear();
+        assert_eq!(StopReason::InputConsumed, cpu.resume().unwrap());
+        assert!(cpu.console.get_log().ends_with(" ok\n"));
+
+        let id = cpu.breakpoints.add(Breakpoint::Instruction(InstructionPattern::AluOp(OpDepth)));
+        match cpu.run(b"1 .s\n".to_vec()).unwrap() {
+            StopReason::Breakpoint(hit) => {
+                assert_eq!(id, hit.id);
+                match cpu.step().unwrap().instruction {
+                    ALU(alu) => assert_eq!(OpDepth, alu.opcode),
+                    ins => panic!("expected ALU, got {:?}", ins),
+                }
+            }
+            reason => panic!("expected breakpoint, got {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn run_bye() {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        assert_eq!(StopReason::Bye, cpu.run(b"1 2 + . bye\n".to_vec()).unwrap());
+        assert!(cpu.console.get_log().ends_with(" 3"));
+
+        // LIT 7002, ALU T N→[T]
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&[0x02, 0xf0, 0x20, 0x60]).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(Err(Error::Bye), cpu.step());
+    }
+
+    #[test]
+    fn limits() {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        cpu.run(b": hang begin again ;\n".to_vec()).unwrap();
+        let cycles = cpu.cycles();
+        assert!(cycles > 0);
+
+        cpu.limits.per_call = Some(10_000);
+        let result = cpu.run(b"hang\n".to_vec());
+        assert_eq!(Err(Error::BudgetExhausted(Budget::PerCall(10_000))), result);
+        assert!(cpu.cycles() <= cycles + 10_000);
+        let result = cpu.resume();
+        assert_eq!(Err(Error::BudgetExhausted(Budget::PerCall(10_000))), result);
+        assert_eq!(cycles + 20_000, cpu.cycles());
+
+        cpu.limits.per_call = None;
+        cpu.limits.max_instructions = Some(cycles + 25_000);
+        let result = cpu.resume();
+        assert_eq!(Err(Error::BudgetExhausted(Budget::Instructions(cycles + 25_000))), result);
+        assert_eq!(cycles + 25_000, cpu.cycles());
+
+        cpu.limits.max_instructions = None;
+        cpu.limits.max_time = Some(Duration::from_millis(10));
+        let result = cpu.resume();
+        assert_eq!(Err(Error::BudgetExhausted(Budget::Time(Duration::from_millis(10)))), result);
+
+        // step is not limited and counts cycles
+        let cycles = cpu.cycles();
+        cpu.step_n(3).unwrap();
+        assert_eq!(cycles + 3, cpu.cycles());
+    }
+
     #[test]
     fn reset() {
         let mut cpu = CPU::new();
@@ -315,25 +1893,59 @@ mod tests {
         let mut cpu = load_binary();
         let mut xs = b"1 2 + .s\n".to_vec();
         cpu.console.load(&mut xs);
-        assert_eq!(16128, cpu.read_at(11));
-        assert_eq!(3650, cpu.read_at(12));
+        assert_eq!(Ok(16128), cpu.read_at(11));
+        assert_eq!(Ok(3650), cpu.read_at(12));
 
         // let mut cmds: Vec<u8> = "1 2 + .s\n".bytes().collect();
         // cpu.console.load(&mut cmds);
 
         // 0x7000 => tx!,  0x7001 => ?rx
-        assert_eq!(1, cpu.read_at(0x7001));
-        assert_eq!('1' as u16, cpu.read_at(0x7000));
-        assert_eq!(' ' as u16, cpu.read_at(0x7000));
-        assert_eq!('2' as u16, cpu.read_at(0x7000));
+        assert_eq!(Ok(1), cpu.read_at(0x7001));
+        assert_eq!(Ok('1' as u16), cpu.read_at(0x7000));
+        assert_eq!(Ok(' ' as u16), cpu.read_at(0x7000));
+        assert_eq!(Ok('2' as u16), cpu.read_at(0x7000));
+
+        cpu.console.load(&mut Vec::new());
+        assert_eq!(Ok(0), cpu.read_at(0x7001));
+    }
+
+    #[test]
+    fn stop_on_idle() {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        cpu.stop_on_idle = true;
+
+        assert_eq!(Ok(StopReason::WaitingForInput), cpu.run(b"2 3 * .\n".to_vec()));
+        assert!(cpu.console.get_log().ends_with(" 6 ok\n"));
+        assert!(!cpu.console.has_input());
+
+        // waits again without new input
+        assert_eq!(Ok(StopReason::WaitingForInput), cpu.resume());
+        assert!(cpu.is_waiting_for_input());
+
+        // the line is evaluated without the padding added by run
+        cpu.console.load(&mut b"4 4 * .\n".to_vec());
+        assert_eq!(Ok(StopReason::WaitingForInput), cpu.resume());
+        assert!(cpu.console.get_log().ends_with(" 10 ok\n"));
+
+        cpu.console.writer.clear();
+        let script = b": sq dup * ;\n7 sq .\n1 2 3 .s\n".to_vec();
+        assert_eq!(Ok(StopReason::WaitingForInput), cpu.run(script));
+        // the console echoes input, numbers are hex
+        assert_eq!(": sq dup * ; ok\n7 sq . 31 ok\n1 2 3 .s\n 1 2 3<tos ok\n", cpu.console.get_writer());
+
+        // ?key and the ?rx read of emit's tx! both see an empty console
+        cpu.console.writer.clear();
+        assert_eq!(Ok(StopReason::WaitingForInput), cpu.run(b": t 3 for ?key drop 41 emit next ; t 99 .\n".to_vec()));
+        assert!(cpu.console.get_writer().ends_with("t 99 .AAAA 99 ok\n"));
     }
 
     #[test]
     fn load_bytes() {
         let mut cpu = CPU::new();
 
-        let data = &mut vec![1, 2, 4, 8];
-        cpu.load_bytes(data).unwrap();
+        let data = [1, 2, 4, 8];
+        cpu.load_bytes(&data).unwrap();
 
         let xs = &cpu.memory[0..2];
         assert_eq!([0x0201, 0x0804], xs);
@@ -373,7 +1985,7 @@ mod tests {
             cpu.st0 = *t;
             cpu.d.push(*n);
             cpu.r.push(*r);
-            assert_eq!(*expected, cpu.new_st0(opcode));
+            assert_eq!(Ok(*expected), cpu.new_st0(opcode));
             // println!("opcode {:?}, t {}, n {}, t {}", opcode, t, n, r);
             cpu.d.pop();
             cpu.r.pop();
@@ -383,7 +1995,7 @@ mod tests {
         cpu.st0 = 0;
         cpu.d.move_sp(0);
         cpu.r.move_sp(4);
-        assert_eq!(1024, cpu.new_st0(&OpDepth));
+        assert_eq!(Ok(1024), cpu.new_st0(&OpDepth));
         // println!("{:?}, d.depth() = {}, r.depth() = {}", OpDepth, cpu.d.depth(), cpu.r.depth());
     }
 
@@ -439,12 +2051,13 @@ mod tests {
 
 
         for (opcode, expected_st0, cpu) in test_cases.iter() {
-            let st0 = cpu.clone().new_st0(opcode);
+            let st0 = cpu.clone().new_st0(opcode).unwrap();
             assert_eq!(*expected_st0, st0)
         }
     }
 
     #[test]
+    #[allow(clippy::field_reassign_with_default)]
     fn eval() {
         let cmp = |expected: &CPU, result: &CPU| {
             assert_eq!(expected.pc, result.pc);
@@ -677,7 +2290,7 @@ mod tests {
         for s in test_cases.iter() {
             let mut cpu = default_cpu.clone();
             for ins in &s.inss {
-                let _ = cpu.execute(&ins);
+                let _ = cpu.execute(ins);
             }
             cmp(&s.e_cpu, &cpu);
         }
@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashSet};
+
+// byte address of the boot image copy of the user area, =uzero in j1.4th
+pub const UZERO: u16 = 0x0002;
+// byte address `cold` copies the user area to, =up in j1.4th
+pub const UP: u16 = 0x3E80;
+
+// user variable offsets in cells, in `u:` order of j1.4th
+const CONTEXT: u16 = 8;
+const DP: u16 = 22;
+const LAST: u16 = 23;
+// wordlist cells in `context`, =vocs in j1.4th
+const VOCS: u16 = 8;
+
+// name field flags, =imed and =comp in j1.4th, the low 5 bits are the length
+const IMMEDIATE: u8 = 0x80;
+const COMPILE_ONLY: u8 = 0x40;
+const LENGTH: u8 = 0x1f;
+
+/// A dictionary header, all addresses are byte addresses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Word {
+    pub name: String,
+    // name field, the counted string after the link field
+    pub name_addr: u16,
+    // name field of the previous word, 0 for the first
+    pub link: u16,
+    // code field, the aligned cell after the name
+    pub code: u16,
+    pub immediate: bool,
+    pub compile_only: bool,
+}
+
+/// Dictionary
+///
+/// The eForth word headers of an image, newest first. Each header is a link
+/// field holding the previous name field, then the name as a counted string
+/// whose length byte also has the immediate and compile-only flags, then the
+/// code. The chains start at `last` and at each wordlist in `context` of the
+/// user area, the run-time copy at `UP` once `cold` has run and the boot
+/// image copy at `UZERO` before.
+///
+/// # Example
+///
+/// ```
+/// use j1::cpu::CPU;
+/// use j1::dictionary::Dictionary;
+/// use j1::j1e_bin;
+///
+/// let mut cpu = CPU::new();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+/// let dictionary = Dictionary::from_memory(cpu.memory());
+/// assert_eq!("cold", dictionary.words()[0].name);
+/// assert_eq!(Some(0x01E2), dictionary.code("dup"));
+///
+/// cpu.run(b": square dup * ;\n".to_vec()).unwrap();
+/// let dictionary = Dictionary::from_memory(cpu.memory());
+/// let square = dictionary.find("square").unwrap();
+/// assert_eq!(Some(square), dictionary.at(square.code));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dictionary {
+    words: Vec<Word>,
+    // `dp` of the user area, the first free byte address
+    here: u16,
+}
+
+impl Dictionary {
+    /// Headers reachable from the user area in `memory`, indexed by cell address
+    pub fn from_memory(memory: &[u16]) -> Self {
+        let up = if cell(memory, UP + LAST * 2) != 0 { UP } else { UZERO };
+        let mut roots = vec![cell(memory, up + LAST * 2)];
+        for i in 0..=VOCS {
+            let wid = cell(memory, up + (CONTEXT + i) * 2);
+            if wid != 0 {
+                roots.push(cell(memory, wid));
+            }
+        }
+
+        let mut dictionary = Dictionary { words: Vec::new(), here: cell(memory, up + DP * 2) };
+        let mut seen = HashSet::new();
+        for root in roots.iter() {
+            dictionary.walk(memory, *root, &mut seen);
+        }
+        dictionary
+    }
+
+    // follows the links from name field `na` until 0 or a header already seen
+    fn walk(&mut self, memory: &[u16], mut na: u16, seen: &mut HashSet<u16>) {
+        while na >= 2 && ((na >> 1) as usize) < memory.len() && seen.insert(na) {
+            let count = byte(memory, na);
+            let len = (count & LENGTH) as u16;
+            let name: String = (1..=len).map(|i| byte(memory, na.wrapping_add(i)) as char).collect();
+            let link = cell(memory, na - 2);
+            self.words.push(Word {
+                name,
+                name_addr: na,
+                link,
+                code: (na + len + 2) & !1,
+                immediate: count & IMMEDIATE != 0,
+                compile_only: count & COMPILE_ONLY != 0,
+            });
+            na = link;
+        }
+    }
+
+    /// Headers, newest first
+    pub fn words(&self) -> &[Word] {
+        &self.words
+    }
+
+    /// First free byte address, `here` in eForth
+    pub fn here(&self) -> u16 {
+        self.here
+    }
+
+    /// Newest header named `name`, the one eForth finds
+    pub fn find(&self, name: &str) -> Option<&Word> {
+        self.words.iter().find(|w| w.name == name)
+    }
+
+    /// Code address of `name`
+    pub fn code(&self, name: &str) -> Option<u16> {
+        self.find(name).map(|w| w.code)
+    }
+
+    /// Newest header whose code starts at byte address `code`
+    pub fn at(&self, code: u16) -> Option<&Word> {
+        self.words.iter().find(|w| w.code == code)
+    }
+
+    /// Header whose code contains byte address `addr`, from its code field up
+    /// to the link field of the next header
+    pub fn within(&self, addr: u16) -> Option<&Word> {
+        let word = self.words.iter().filter(|w| w.code <= addr).max_by_key(|w| w.code)?;
+        let next = self.words.iter().any(|w| w.name_addr > word.code && w.name_addr - 2 <= addr);
+        if next { None } else { Some(word) }
+    }
+
+    /// Symbol table of word name to code address, redefinitions resolve to
+    /// the newest header
+    pub fn symbols(&self) -> BTreeMap<String, u16> {
+        self.words.iter().rev().map(|w| (w.name.clone(), w.code)).collect()
+    }
+}
+
+// cell at byte address `addr`, 0 outside memory
+pub(crate) fn cell(memory: &[u16], addr: u16) -> u16 {
+    memory.get((addr >> 1) as usize).copied().unwrap_or(0)
+}
+
+// byte at byte address `addr`, cells are little endian
+pub(crate) fn byte(memory: &[u16], addr: u16) -> u8 {
+    (cell(memory, addr) >> ((addr & 1) * 8)) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::CPU;
+    use crate::dictionary::*;
+    use crate::j1e_bin;
+
+    #[test]
+    fn dictionary() {
+        let mut cpu = CPU::new();
+        assert!(Dictionary::from_memory(cpu.memory()).words().is_empty());
+
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        let boot = Dictionary::from_memory(cpu.memory());
+        assert_eq!(276, boot.words().len());
+        assert_eq!("noop", boot.words()[275].name);
+        assert_eq!(0, boot.words()[275].link);
+        assert_eq!(0x1A04, boot.here());
+
+        // name, name field, code field, immediate, compile-only
+        let test_cases = [
+            ("dup", 0x01DE, 0x01E2, false, false),
+            ("cold", 0x19CE, 0x19D4, false, false),
+            ("dovar", 0x0390, 0x0396, false, true),
+            ("(", 0x0BF0, 0x0BF2, true, false),
+            (";", 0x14B2, 0x14B4, true, true),
+            (".\"|", 0x0A6A, 0x0A6E, false, true),
+        ];
+        for (name, name_addr, code, immediate, compile_only) in test_cases.iter() {
+            let word = boot.find(name).unwrap();
+            assert_eq!((*name_addr, *code, *immediate, *compile_only),
+                       (word.name_addr, word.code, word.immediate, word.compile_only), "{}", name);
+            assert_eq!(Some(word), boot.at(*code));
+        }
+        assert_eq!(None, boot.find("square"));
+
+        // code from the code field up to the next header
+        let test_cases = [(0x19D4, Some("cold")), (0x19DA, Some("cold")), (0x1994, Some("ver")), (0x1996, None), (0x00C4, None)];
+        for (addr, expected) in test_cases.iter() {
+            assert_eq!(*expected, boot.within(*addr).map(|w| w.name.as_str()), "{:04X}", addr);
+        }
+
+        // cold moves the user area to UP, new words chain onto the boot image
+        cpu.run(b": square dup * ; : dup square ; immediate\n".to_vec()).unwrap();
+        let run = Dictionary::from_memory(cpu.memory());
+        assert_eq!(278, run.words().len());
+        assert_eq!(vec!["dup", "square", "cold"], run.words().iter().take(3).map(|w| w.name.as_str()).collect::<Vec<_>>());
+        assert!(run.words()[0].immediate);
+        assert_eq!(run.words()[1].name_addr, run.words()[0].link);
+        assert_eq!(Some(run.words()[0].code), run.code("dup"));
+        assert_eq!(Some(&run.words()[0].code), run.symbols().get("dup"));
+        assert_eq!(277, run.symbols().len());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::dictionary::{byte, cell, Dictionary, UZERO};
+use crate::instruction::decode;
+use crate::instruction::Instruction::{Call, Jump};
+
+// end of the boot image user area, =pick in j1.4th
+const PICK: u16 = 0x0080;
+
+// words that return past the counted string compiled after their call by
+// `$literal` in j1.4th
+const STRING_WORDS: [&str; 3] = ["$\"|", ".\"|", "<?abort\">"];
+
+/// What a memory cell holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellKind {
+    // an instruction
+    Code,
+    // link or name field of a dictionary header
+    Header,
+    // counted string inline after a call to `."|`, `$"|` or `<?abort">`
+    String,
+    // user area, CREATE and does> bodies and the free memory from `here` up
+    Data,
+}
+
+/// Classifies every cell of `memory`, indexed by cell address
+///
+/// Cells are code unless the dictionary says otherwise: headers come from
+/// the link chains, a word whose code field calls `dovar` is followed by its
+/// CREATE body, and a word whose code field jumps to a literal of its own
+/// body is a does> word with the body up to that literal. Memory without a
+/// dictionary is all code.
+///
+/// # Example
+///
+/// ```
+/// use j1::cpu::CPU;
+/// use j1::dictionary::Dictionary;
+/// use j1::disasm::{classify, CellKind};
+/// use j1::j1e_bin;
+///
+/// let mut cpu = CPU::new();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+/// cpu.run(b"variable x\n".to_vec()).unwrap();
+///
+/// let dictionary = Dictionary::from_memory(cpu.memory());
+/// let kinds = classify(cpu.memory(), &dictionary);
+/// let x = dictionary.code("x").unwrap();
+/// assert_eq!(CellKind::Header, kinds[(x >> 1) as usize - 1]);
+/// assert_eq!(CellKind::Code, kinds[(x >> 1) as usize]);
+/// assert_eq!(CellKind::Data, kinds[(x >> 1) as usize + 1]);
+/// ```
+pub fn classify(memory: &[u16], dictionary: &Dictionary) -> Vec<CellKind> {
+    let mut kinds = vec![CellKind::Code; memory.len()];
+    let words = dictionary.words();
+    if words.is_empty() {
+        return kinds;
+    }
+
+    mark(&mut kinds, UZERO, PICK as usize, CellKind::Data);
+    let here = dictionary.here();
+    mark(&mut kinds, here, memory.len() << 1, CellKind::Data);
+
+    let dovar = dictionary.code("dovar");
+    for w in words.iter() {
+        mark(&mut kinds, w.name_addr - 2, w.code as usize, CellKind::Header);
+
+        // the body ends at the next header or at here
+        let end = words.iter()
+            .map(|x| x.name_addr - 2)
+            .filter(|x| *x > w.code)
+            .min()
+            .unwrap_or_else(|| here.max(w.code));
+        match decode(cell(memory, w.code)) {
+            Ok(Call(target)) if Some(target << 1) == dovar => {
+                mark(&mut kinds, w.code + 2, end as usize, CellKind::Data);
+            }
+            Ok(Jump(target)) if cell(memory, target << 1) == 0x8000 | (w.code + 2) => {
+                mark(&mut kinds, w.code + 2, (target << 1).min(end) as usize, CellKind::Data);
+            }
+            _ => (),
+        }
+    }
+
+    let strings: Vec<u16> = STRING_WORDS.iter().filter_map(|name| dictionary.code(name)).collect();
+    let mut i = 0;
+    while i < kinds.len() {
+        let call = match decode(memory[i]) {
+            Ok(Call(target)) => strings.contains(&(target << 1)),
+            _ => false,
+        };
+        if kinds[i] != CellKind::Code || !call {
+            i += 1;
+            continue;
+        }
+        let addr = ((i + 1) << 1) as u16;
+        let cells = (byte(memory, addr) as usize + 2) >> 1;
+        for kind in kinds.iter_mut().skip(i + 1).take(cells) {
+            if *kind == CellKind::Code {
+                *kind = CellKind::String;
+            }
+        }
+        i += cells + 1;
+    }
+    kinds
+}
+
+// marks the cells of byte addresses `from` up to `to`
+fn mark(kinds: &mut [CellKind], from: u16, to: usize, kind: CellKind) {
+    let to = ((to + 1) >> 1).min(kinds.len());
+    for x in kinds.iter_mut().take(to).skip((from >> 1) as usize) {
+        *x = kind;
+    }
+}
+
+/// Counted string at byte address `addr`, see `quoted`
+pub fn counted(memory: &[u16], addr: u16) -> String {
+    let len = byte(memory, addr) as u16;
+    quoted((1..=len).map(|i| byte(memory, addr.wrapping_add(i)) as char))
+}
+
+/// Quoted text with `\"`, `\\` and `\xNN` escapes
+pub fn quoted<I: IntoIterator<Item=char>>(chars: I) -> String {
+    let mut s = String::from("\"");
+    for c in chars {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            ' '..='~' => s.push(c),
+            c => s.push_str(&format!("\\x{:02X}", c as u32)),
+        }
+    }
+    s.push('"');
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::CPU;
+    use crate::dictionary::Dictionary;
+    use crate::disasm::*;
+    use crate::j1e_bin;
+
+    #[test]
+    fn cell_kinds() {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        let dictionary = Dictionary::from_memory(cpu.memory());
+        let kinds = classify(cpu.memory(), &dictionary);
+
+        // byte address and kind
+        let test_cases = [
+            (0x0000, CellKind::Code),
+            (0x0002, CellKind::Data),
+            (0x007E, CellKind::Data),
+            (0x0080, CellKind::Code),
+            // link and name of dup
+            (0x01DC, CellKind::Header),
+            (0x01E0, CellKind::Header),
+            (0x01E2, CellKind::Code),
+            // up: CALL dovar, =up
+            (0x03A0, CellKind::Code),
+            (0x03A2, CellKind::Data),
+            (0x19D4, CellKind::Code),
+            (0x1A04, CellKind::Data),
+        ];
+        for (addr, expected) in test_cases.iter() {
+            assert_eq!(*expected, kinds[addr >> 1], "{:04X}", addr);
+        }
+
+        // `."| $literal  ok` in .ok
+        let ok = (0..kinds.len()).find(|i| counted(cpu.memory(), (*i << 1) as u16) == "\" ok\"").unwrap();
+        assert_eq!((CellKind::Code, CellKind::String, CellKind::String, CellKind::Code),
+                   (kinds[ok - 1], kinds[ok], kinds[ok + 1], kinds[ok + 2]));
+
+        // a does> body ends at the literal it jumps to
+        cpu.run(b"1234 constant k create buf 5 , 6 , : two 2 ;\n".to_vec()).unwrap();
+        let dictionary = Dictionary::from_memory(cpu.memory());
+        let kinds = classify(cpu.memory(), &dictionary);
+        let k = (dictionary.code("k").unwrap() >> 1) as usize;
+        assert_eq!(vec![CellKind::Code, CellKind::Data, CellKind::Code, CellKind::Code, CellKind::Header], kinds[k..k + 5].to_vec());
+        let buf = (dictionary.code("buf").unwrap() >> 1) as usize;
+        assert_eq!(vec![CellKind::Code, CellKind::Data, CellKind::Data, CellKind::Header], kinds[buf..buf + 4].to_vec());
+        let two = (dictionary.code("two").unwrap() >> 1) as usize;
+        assert_eq!(vec![CellKind::Code, CellKind::Code, CellKind::Data], kinds[two..two + 3].to_vec());
+
+        assert_eq!("\"a\\\"\\\\\\x07\"", counted(&[0x6104, 0x5c22, 0x0007], 0));
+        assert!(classify(&[0x6104; 16], &Dictionary::default()).iter().all(|x| *x == CellKind::Code));
+    }
+}
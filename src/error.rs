@@ -0,0 +1,109 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::breakpoint::BreakpointHit;
+use crate::stack::{StackFaultKind, StackKind};
+
+/// Execution limit that ran out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Budget {
+    // total instructions executed by the CPU
+    Instructions(u64),
+    // instructions executed by a single run call
+    PerCall(u64),
+    // wall-clock time of a single run call
+    Time(Duration),
+}
+
+/// Error
+///
+/// Everything that can stop the CPU other than running out of console input.
+/// `Bye` is a normal halt, `CPU::run` reports it as `StopReason::Bye`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    // `bye` was executed
+    Bye,
+    InvalidInstruction(u16),
+    // stack overflow or underflow trapped at `pc`
+    StackFault { pc: u16, stack: StackKind, kind: StackFaultKind },
+    BudgetExhausted(Budget),
+    Breakpoint(BreakpointHit),
+    // memory mapped device failure
+    Io { addr: u16, message: String },
+    // reverse execution to a cycle outside the recorded history
+    NotRecorded(u64),
+    // instruction text that `Instruction::from_str` could not parse
+    InvalidAsm(String),
+}
+
+impl Error {
+    /// Process exit code for the executables, 0 for a normal halt
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Bye => 0,
+            Error::InvalidInstruction(_) => 2,
+            Error::StackFault { .. } => 3,
+            Error::BudgetExhausted(_) => 4,
+            Error::Breakpoint(_) => 5,
+            Error::Io { .. } => 6,
+            Error::NotRecorded(_) => 7,
+            Error::InvalidAsm(_) => 8,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Bye => write!(f, "bye"),
+            Error::InvalidInstruction(v) => write!(f, "Invalid Instruction: {:0>4x}", v),
+            Error::StackFault { pc, stack, kind } => {
+                write!(f, "{:?} stack {:?} at 0x{:04X}", stack, kind, pc << 1)
+            }
+            Error::BudgetExhausted(Budget::Instructions(n)) => write!(f, "instruction limit of {} exhausted", n),
+            Error::BudgetExhausted(Budget::PerCall(n)) => write!(f, "per call limit of {} instructions exhausted", n),
+            Error::BudgetExhausted(Budget::Time(d)) => write!(f, "time limit of {:?} exhausted", d),
+            Error::Breakpoint(hit) => write!(f, "breakpoint {} hit at 0x{:04X}", hit.id, hit.pc << 1),
+            Error::Io { addr, message } => write!(f, "i/o error at 0x{:04X}: {}", addr, message),
+            Error::NotRecorded(cycle) => write!(f, "cycle {} is not in the recorded history", cycle),
+            Error::InvalidAsm(s) => write!(f, "Invalid instruction: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        std::io::Error::other(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::*;
+
+    #[test]
+    fn display() {
+        let test_cases = [
+            (Error::Bye, "bye"),
+            (Error::InvalidInstruction(0x12), "Invalid Instruction: 0012"),
+            (Error::StackFault { pc: 0x10, stack: StackKind::Data, kind: StackFaultKind::Underflow }, "Data stack Underflow at 0x0020"),
+            (Error::BudgetExhausted(Budget::PerCall(5)), "per call limit of 5 instructions exhausted"),
+            (Error::Io { addr: 0x7000, message: "closed".to_string() }, "i/o error at 0x7000: closed"),
+            (Error::NotRecorded(12), "cycle 12 is not in the recorded history"),
+            (Error::InvalidAsm("ALU T T".to_string()), "Invalid instruction: ALU T T"),
+        ];
+        for (e, expected) in test_cases.iter() {
+            assert_eq!(*expected, e.to_string());
+        }
+    }
+
+    #[test]
+    fn exit_code() {
+        assert_eq!(0, Error::Bye.exit_code());
+        assert_ne!(0, Error::InvalidInstruction(0).exit_code());
+        let e: std::io::Error = Error::Bye.into();
+        assert_eq!("bye", e.to_string());
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+
+use crate::breakpoint::Breakpoint;
+use crate::cpu::{StopReason, CPU};
+use crate::error::Error;
+
+const TARGET_XML: &str = concat!(
+    r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd">"#,
+    r#"<target><feature name="org.j1.cpu">"#,
+    r#"<reg name="pc" bitsize="16" type="code_ptr" regnum="0"/>"#,
+    r#"<reg name="t" bitsize="16" type="uint16"/>"#,
+    r#"<reg name="dsp" bitsize="16" type="uint16"/>"#,
+    r#"<reg name="rsp" bitsize="16" type="uint16"/>"#,
+    r#"</feature></target>"#,
+);
+
+/// GdbServer
+///
+/// GDB remote serial protocol stub for the J1 CPU
+///
+/// Registers are pc (byte address), T, dsp and rsp, all 16 bit little endian.
+/// Memory is the 16 bit cell array seen as little endian bytes.
+/// `continue` runs until a breakpoint or until the console input is consumed,
+/// console input is queued with `monitor input <text>` and console output
+/// is sent back as `O` packets.
+///
+/// # Example
+///
+/// ```no_run
+/// use j1::cpu::CPU;
+/// use j1::gdb;
+/// use j1::j1e_bin;
+///
+/// let mut cpu = CPU::new();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+///
+/// // (gdb) target remote localhost:2331
+/// let _cpu = gdb::serve_tcp(cpu, 2331).unwrap();
+/// ```
+pub struct GdbServer<S: Read + Write> {
+    cpu: CPU,
+    stream: S,
+    no_ack: bool,
+    // (Z packet type, byte address) => breakpoint id
+    breaks: HashMap<(u8, u16), usize>,
+}
+
+impl<S: Read + Write> GdbServer<S> {
+    pub fn new(cpu: CPU, stream: S) -> Self {
+        GdbServer { cpu, stream, no_ack: false, breaks: HashMap::new() }
+    }
+
+    /// Serves packets until the client kills, detaches or disconnects
+    pub fn serve(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet)? {
+                Some(reply) => self.send_packet(&reply)?,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_cpu(self) -> CPU {
+        self.cpu
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut b = [0u8];
+        match self.stream.read(&mut b)? {
+            0 => Ok(None),
+            _ => Ok(Some(b[0])),
+        }
+    }
+
+    // reads one `$data#cs` packet, acks and interrupts outside packets are skipped
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => (),
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                }
+            }
+            let mut cs = [0u8; 2];
+            self.stream.read_exact(&mut cs)?;
+            let expected = std::str::from_utf8(&cs).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+            let ok = expected == Some(checksum(&data));
+            if !self.no_ack {
+                self.stream.write_all(if ok { b"+" } else { b"-" })?;
+                self.stream.flush()?;
+            }
+            if ok {
+                return Ok(Some(String::from_utf8_lossy(&data).to_string()));
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            self.stream.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // reply to a packet, None ends the session
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let (cmd, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match cmd {
+            "?" => "S05".to_string(),
+            "g" => self.registers().iter().map(|v| hex_u16(*v)).collect(),
+            "G" => self.write_registers(args),
+            "p" => match usize::from_str_radix(args, 16).ok().and_then(|n| self.registers().get(n).copied()) {
+                Some(v) => hex_u16(v),
+                None => "E01".to_string(),
+            },
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "c" | "s" => {
+                if let Some(pc) = parse_hex(args) {
+                    self.cpu.set_pc(pc >> 1);
+                }
+                let result = if cmd == "s" { self.cpu.step().map(|_| StopReason::InputConsumed) } else { self.cpu.resume() };
+                self.send_output()?;
+                match result {
+                    Ok(StopReason::Bye) | Err(Error::Bye) => "W00".to_string(),
+                    Ok(_) => "S05".to_string(),
+                    Err(_) => "S04".to_string(),
+                }
+            }
+            "Z" | "z" => self.breakpoint(cmd == "Z", args),
+            "k" => return Ok(None),
+            "D" => {
+                self.send_packet("OK")?;
+                return Ok(None);
+            }
+            "H" => "OK".to_string(),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=1000;QStartNoAckMode+;qXfer:features:read+".to_string()
+        } else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            "OK".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = match parse_pair(range) {
+                Some(x) => x,
+                None => return "E01".to_string(),
+            };
+            let xml = TARGET_XML.as_bytes();
+            let start = offset.min(xml.len());
+            let end = (start + len).min(xml.len());
+            let prefix = if end == xml.len() { "l" } else { "m" };
+            format!("{}{}", prefix, String::from_utf8_lossy(&xml[start..end]))
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else if let Some(hex) = packet.strip_prefix("qRcmd,") {
+            self.monitor(&String::from_utf8_lossy(&decode_hex(hex).unwrap_or_default()))
+        } else {
+            String::new()
+        }
+    }
+
+    // `monitor input <text>` queues a line of console input after any pending input
+    fn monitor(&mut self, command: &str) -> String {
+        match command.split_once(' ') {
+            Some(("input", text)) => {
+                let mut xs = text.as_bytes().to_vec();
+                xs.push(b'\n');
+                self.cpu.append_input(xs);
+                "OK".to_string()
+            }
+            _ => encode_hex(b"monitor commands: input <text>\n"),
+        }
+    }
+
+    fn registers(&self) -> Vec<u16> {
+        let s = self.cpu.state();
+        vec![s.pc << 1, s.t, s.data_depth, s.return_depth]
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let xs = match decode_hex(args) {
+            Some(xs) if xs.len() == 8 => xs,
+            _ => return "E01".to_string(),
+        };
+        let regs: Vec<u16> = xs.chunks(2).map(|b| u16::from_le_bytes([b[0], b[1]])).collect();
+        let current = self.registers();
+        if regs[2..] != current[2..] {
+            return "E01".to_string();
+        }
+        self.cpu.set_pc(regs[0] >> 1);
+        self.cpu.set_t(regs[1]);
+        "OK".to_string()
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let value = args.split_once('=').and_then(|(n, v)| {
+            let n = usize::from_str_radix(n, 16).ok()?;
+            let b = decode_hex(v)?;
+            if b.len() != 2 { None } else { Some((n, u16::from_le_bytes([b[0], b[1]]))) }
+        });
+        match value {
+            Some((0, v)) => self.cpu.set_pc(v >> 1),
+            Some((1, v)) => self.cpu.set_t(v),
+            _ => return "E01".to_string(),
+        }
+        "OK".to_string()
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let (addr, len) = match parse_pair(args) {
+            Some(x) => x,
+            None => return "E01".to_string(),
+        };
+        let memory = self.cpu.memory();
+        match addr.checked_add(len) {
+            Some(end) if end <= memory.len() * 2 => (),
+            _ => return "E01".to_string(),
+        }
+        let bytes: Vec<u8> = (addr..addr + len)
+            .map(|a| memory[a >> 1].to_le_bytes()[a & 1])
+            .collect();
+        encode_hex(&bytes)
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let parsed = args.split_once(':').and_then(|(range, data)| Some((parse_pair(range)?, decode_hex(data)?)));
+        let ((addr, len), data) = match parsed {
+            Some(x) => x,
+            None => return "E01".to_string(),
+        };
+        match addr.checked_add(len) {
+            Some(end) if data.len() == len && end <= self.cpu.memory().len() * 2 => (),
+            _ => return "E01".to_string(),
+        }
+        for (i, b) in data.iter().enumerate() {
+            let a = addr + i;
+            let mut bytes = self.cpu.memory()[a >> 1].to_le_bytes();
+            bytes[a & 1] = *b;
+            self.cpu.write_memory(a >> 1, u16::from_le_bytes(bytes));
+        }
+        "OK".to_string()
+    }
+
+    // Z0/z0 software breakpoints, Z2/Z3 write and read watchpoints
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut xs = args.split(',');
+        let kind = xs.next().and_then(|k| k.parse::<u8>().ok());
+        let addr = xs.next().and_then(parse_hex);
+        let (kind, addr) = match (kind, addr) {
+            (Some(k), Some(a)) if k == 0 || k == 1 || k == 2 || k == 3 => (k, a),
+            _ => return String::new(),
+        };
+        if insert {
+            let bp = match kind {
+                0 | 1 => Breakpoint::Pc(addr >> 1),
+                2 => Breakpoint::Write(addr),
+                _ => Breakpoint::Read(addr),
+            };
+            if !self.breaks.contains_key(&(kind, addr)) {
+                let id = self.cpu.breakpoints.add(bp);
+                self.breaks.insert((kind, addr), id);
+            }
+        } else if let Some(id) = self.breaks.remove(&(kind, addr)) {
+            self.cpu.breakpoints.remove(id);
+        }
+        "OK".to_string()
+    }
+
+    // console output as `O` packets
+    fn send_output(&mut self) -> io::Result<()> {
+        if !self.cpu.console.writer.is_empty() {
+            let out = format!("O{}", encode_hex(&self.cpu.console.writer));
+            self.cpu.console.writer.clear();
+            self.send_packet(&out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Waits for a single gdb connection on localhost:`port` and serves it
+pub fn serve_tcp(cpu: CPU, port: u16) -> io::Result<CPU> {
+    serve_listener(cpu, TcpListener::bind(("127.0.0.1", port))?)
+}
+
+/// Serves the first connection accepted by `listener`
+pub fn serve_listener(cpu: CPU, listener: TcpListener) -> io::Result<CPU> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    let mut server = GdbServer::new(cpu, stream);
+    server.serve()?;
+    Ok(server.into_cpu())
+}
+
+/// Serves gdb over stdin/stdout, e.g. `target remote | j1_gdb --stdio`
+pub fn serve_stdio(cpu: CPU) -> io::Result<CPU> {
+    let mut server = GdbServer::new(cpu, Stdio);
+    server.serve()?;
+    Ok(server.into_cpu())
+}
+
+struct Stdio;
+
+impl Read for Stdio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::stdin().read(buf)
+    }
+}
+
+impl Write for Stdio {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stdout().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |cs, b| cs.wrapping_add(*b))
+}
+
+fn hex_u16(v: u16) -> String {
+    encode_hex(&v.to_le_bytes())
+}
+
+fn encode_hex(xs: &[u8]) -> String {
+    xs.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    u16::from_str_radix(s, 16).ok()
+}
+
+fn parse_pair(s: &str) -> Option<(usize, usize)> {
+    let (a, b) = s.split_once(',')?;
+    Some((usize::from_str_radix(a, 16).ok()?, usize::from_str_radix(b, 16).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use crate::cpu::CPU;
+    use crate::gdb::*;
+    use crate::j1e_bin;
+
+    // scripted RSP client
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+            let mut ack = [0u8];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(b'+', ack[0]);
+            self.reply()
+        }
+
+        fn reply(&mut self) -> String {
+            let mut b = [0u8];
+            loop {
+                self.stream.read_exact(&mut b).unwrap();
+                if b[0] == b'$' {
+                    break;
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                self.stream.read_exact(&mut b).unwrap();
+                if b[0] == b'#' {
+                    break;
+                }
+                data.push(b[0]);
+            }
+            let mut cs = [0u8; 2];
+            self.stream.read_exact(&mut cs).unwrap();
+            assert_eq!(format!("{:02x}", checksum(&data)), String::from_utf8_lossy(&cs));
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(data).unwrap()
+        }
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!("0a00ff", encode_hex(&[10, 0, 255]));
+        assert_eq!(Some(vec![10, 0, 255]), decode_hex("0a00ff"));
+        assert_eq!(None, decode_hex("0a0"));
+        assert_eq!("3412", hex_u16(0x1234));
+        assert_eq!(Some((0x10, 4)), parse_pair("10,4"));
+        assert_eq!(0x37, checksum(b"qSupported"));
+    }
+
+    #[test]
+    fn session() {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || serve_listener(cpu, listener).unwrap());
+
+        let mut c = Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap() };
+        assert!(c.request("qSupported:swbreak+").contains("qXfer:features:read+"));
+        assert!(c.request("qXfer:features:read:target.xml:0,1000").starts_with("l<?xml"));
+        assert_eq!("S05", c.request("?"));
+        assert_eq!("0000000000000000", c.request("g"));
+
+        // memory is little endian bytes of the 16 bit cells
+        assert_eq!("ea0c1000", c.request("m0,4"));
+        assert_eq!("E01", c.request("m3ffe,4"));
+        assert_eq!("E01", c.request("mffffffffffffffff,2"));
+        assert_eq!("E01", c.request("Mffffffffffffffff,2:3412"));
+        assert_eq!("OK", c.request("M3ffc,2:3412"));
+        assert_eq!("3412", c.request("m3ffc,2"));
+
+        // single step: UBRANCH 19D4
+        assert_eq!("S05", c.request("s"));
+        assert_eq!("d419", c.request("p0"));
+
+        // run to a breakpoint, console output arrives as O packets
+        assert_eq!("OK", c.request("Z0,d90,2"));
+        assert_eq!("OK", c.request(&format!("qRcmd,{}", encode_hex(b"input 2 3"))));
+        assert_eq!("OK", c.request(&format!("qRcmd,{}", encode_hex(b"input * ."))));
+        let reply = c.request("c");
+        assert!(reply.starts_with('O'));
+        let out = String::from_utf8(decode_hex(&reply[1..]).unwrap()).unwrap();
+        assert!(out.contains("eforth j1 v1.04"));
+        assert_eq!("S05", c.reply());
+        assert_eq!("900d", c.request("p0"));
+        assert_eq!("OK", c.request("z0,d90,2"));
+
+        assert_eq!("OK", c.request("P1=3412"));
+        assert_eq!("3412", c.request("p1"));
+        assert_eq!("", c.request("vMustReplyEmpty"));
+        c.stream.write_all(b"$k#6b").unwrap();
+
+        let cpu = server.join().unwrap();
+        assert_eq!(0x1234, cpu.state().t);
+        assert_eq!(0x1234, cpu.memory()[0x1ffe]);
+        assert_eq!(b"2 3\n* .\n".to_vec(), *cpu.console.reader.get_ref());
+    }
+}
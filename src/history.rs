@@ -0,0 +1,319 @@
+use crate::cpu::{Access, StepResult, CPU};
+use crate::error::Error;
+use crate::snapshot::{ConsoleState, Snapshot};
+
+/// History
+///
+/// Execution history for reverse debugging. A checkpoint `Snapshot` is taken
+/// every `interval` instructions and console input is recorded with the cycle
+/// it was loaded at, so any recorded cycle can be rebuilt by restoring the
+/// nearest earlier checkpoint and replaying. Changes made through
+/// `CPU::load_input`, `CPU::write_memory`, `CPU::set_pc` and `CPU::set_t` are
+/// recorded, state of mapped i/o devices is not.
+///
+/// # Example
+///
+/// ```
+/// use j1::cpu::CPU;
+/// use j1::history::History;
+/// use j1::j1e_bin;
+///
+/// let mut cpu = CPU::new();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+/// cpu.history = Some(History::new(1000));
+/// cpu.run(b"variable x 1234 x !\n".to_vec()).unwrap();
+///
+/// let before = cpu.cycles();
+/// cpu.run(b"4321 x !\n".to_vec()).unwrap();
+/// let x = cpu.memory().iter().position(|v| *v == 0x4321).unwrap() as u16;
+///
+/// // back to the `!` that stored 4321
+/// let cycle = cpu.reverse_to_write(x << 1).unwrap().unwrap();
+/// assert!(cycle > before);
+/// assert_eq!(0x1234, cpu.memory()[x as usize]);
+/// cpu.step().unwrap();
+/// assert_eq!(0x4321, cpu.memory()[x as usize]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct History {
+    interval: u64,
+    // oldest checkpoints are dropped past this, None keeps everything
+    capacity: Option<usize>,
+    // ordered by cycle
+    checkpoints: Vec<Snapshot>,
+    // console state after each input load, ordered by cycle
+    inputs: Vec<(u64, ConsoleState)>,
+    // next input to apply while replaying
+    cursor: usize,
+}
+
+impl History {
+    /// History with a checkpoint every `interval` instructions
+    pub fn new(interval: u64) -> Self {
+        History { interval: interval.max(1), capacity: None, checkpoints: Vec::new(), inputs: Vec::new(), cursor: 0 }
+    }
+
+    /// History that keeps only the last `n` checkpoints
+    pub fn bounded(interval: u64, n: usize) -> Self {
+        History { capacity: Some(n.max(1)), ..History::new(interval) }
+    }
+
+    /// Earliest cycle that can be reached, None before the first step
+    pub fn start(&self) -> Option<u64> {
+        self.checkpoints.first().map(|s| s.cycles)
+    }
+
+    pub fn checkpoints(&self) -> impl Iterator<Item=&Snapshot> {
+        self.checkpoints.iter()
+    }
+
+    // called by the CPU before executing the instruction at `cpu.cycles()`
+    pub(crate) fn before_step(&mut self, cpu: &mut CPU) {
+        let now = cpu.cycles();
+        while self.cursor < self.inputs.len() && self.inputs[self.cursor].0 <= now {
+            cpu.console = self.inputs[self.cursor].1.to_console();
+            self.cursor += 1;
+        }
+        let due = match self.checkpoints.last() {
+            None => true,
+            Some(s) => now >= s.cycles + self.interval,
+        };
+        if due {
+            self.push(cpu.snapshot());
+        }
+    }
+
+    // called by the CPU after console input is loaded
+    pub(crate) fn record_input(&mut self, cpu: &CPU) {
+        let now = cpu.cycles();
+        self.truncate(now);
+        self.inputs.push((now, ConsoleState::new(&cpu.console)));
+        self.cursor = self.inputs.len();
+    }
+
+    // called by the CPU after memory or registers are changed directly
+    pub(crate) fn record_edit(&mut self, cpu: &CPU) {
+        let now = cpu.cycles();
+        self.truncate(now);
+        self.checkpoints.retain(|s| s.cycles < now);
+        self.push(cpu.snapshot());
+    }
+
+    // forgets the recorded future after `now`, which the change rewrites
+    fn truncate(&mut self, now: u64) {
+        self.checkpoints.retain(|s| s.cycles <= now);
+        self.inputs.retain(|(c, _)| *c <= now);
+        self.cursor = self.cursor.min(self.inputs.len());
+    }
+
+    fn push(&mut self, snapshot: Snapshot) {
+        self.checkpoints.push(snapshot);
+        if let Some(n) = self.capacity {
+            if self.checkpoints.len() > n {
+                self.checkpoints.remove(0);
+                let start = self.checkpoints[0].cycles;
+                let before = self.inputs.iter().filter(|(c, _)| *c < start).count();
+                self.inputs.drain(..before);
+                self.cursor = self.cursor.saturating_sub(before);
+            }
+        }
+    }
+}
+
+/// Reverse execution, these fail with `Error::NotRecorded` if the CPU has no
+/// history or the cycle is before the oldest checkpoint
+impl CPU {
+    /// Moves to the state just before the instruction at `cycle` executes,
+    /// cycles past the present are run forward
+    pub fn goto_cycle(&mut self, cycle: u64) -> Result<(), Error> {
+        let mut history = self.history.take().ok_or(Error::NotRecorded(cycle))?;
+        // restore the latest checkpoint at or before `cycle`, unless running
+        // forward from the current state is shorter
+        let restored = match history.checkpoints.iter().rposition(|s| s.cycles <= cycle) {
+            Some(i) if cycle < self.cycles() || history.checkpoints[i].cycles > self.cycles() => {
+                let start = history.checkpoints[i].cycles;
+                history.cursor = history.inputs.iter().filter(|(c, _)| *c < start).count();
+                self.restore(&history.checkpoints[i]).map_err(|_| Error::NotRecorded(cycle))
+            }
+            Some(_) => Ok(()),
+            None => Err(Error::NotRecorded(cycle)),
+        };
+        self.history = Some(history);
+        restored?;
+
+        self.replay(|cpu| {
+            while cpu.cycles() < cycle {
+                cpu.step()?;
+            }
+            Ok(())
+        })
+    }
+
+    // runs `f` with the tracer and the waveform detached, replayed
+    // instructions were recorded the first time through
+    fn replay<T, F: FnOnce(&mut CPU) -> T>(&mut self, f: F) -> T {
+        let tracer = self.tracer.take();
+        let waveform = self.waveform.take();
+        let result = f(self);
+        self.tracer = tracer;
+        self.waveform = waveform;
+        result
+    }
+
+    /// Moves back `n` instructions
+    pub fn step_back(&mut self, n: u64) -> Result<(), Error> {
+        let cycle = self.cycles().checked_sub(n).ok_or(Error::NotRecorded(0))?;
+        self.goto_cycle(cycle)
+    }
+
+    /// Moves back to the most recent instruction that wrote the memory cell
+    /// at byte address `addr`, stopping before it executes. Returns its cycle,
+    /// or None with the CPU unchanged if no recorded instruction wrote `addr`.
+    pub fn reverse_to_write(&mut self, addr: u16) -> Result<Option<u64>, Error> {
+        self.reverse_until(|step| match step.write {
+            Some(Access::Memory { addr: a, .. }) => a >> 1 == addr >> 1,
+            _ => false,
+        })
+    }
+
+    /// Moves back to the most recent instruction whose `StepResult` matches
+    /// `pred`, stopping before it executes
+    pub fn reverse_until<F: Fn(&StepResult) -> bool>(&mut self, pred: F) -> Result<Option<u64>, Error> {
+        let now = self.cycles();
+        let starts: Vec<u64> = match &self.history {
+            Some(h) => h.checkpoints.iter().map(|s| s.cycles).filter(|c| *c < now).collect(),
+            None => return Err(Error::NotRecorded(now)),
+        };
+
+        // replay one checkpoint interval at a time, newest first
+        let mut end = now;
+        for start in starts.into_iter().rev() {
+            self.goto_cycle(start)?;
+            let found = self.replay(|cpu| {
+                let mut found = None;
+                while cpu.cycles() < end {
+                    let cycle = cpu.cycles();
+                    match cpu.step() {
+                        Ok(step) if pred(&step) => found = Some(cycle),
+                        Ok(_) => (),
+                        Err(_) => break,
+                    }
+                }
+                found
+            });
+            if let Some(cycle) = found {
+                self.goto_cycle(cycle)?;
+                return Ok(Some(cycle));
+            }
+            end = start;
+        }
+        self.goto_cycle(now)?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::CPU;
+    use crate::error::Error;
+    use crate::history::*;
+    use crate::j1e_bin;
+    use crate::vcd::Waveform;
+
+    fn cpu_with_history(history: History) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        cpu.stop_on_idle = true;
+        cpu.history = Some(history);
+        cpu
+    }
+
+    #[test]
+    fn goto_and_step_back() {
+        let mut cpu = cpu_with_history(History::new(500));
+        cpu.run(b"1 2 +\n".to_vec()).unwrap();
+        let mid = cpu.cycles();
+        let mid_snapshot = cpu.snapshot();
+        cpu.run(b"dup * .\n".to_vec()).unwrap();
+        let end = cpu.cycles();
+        let end_snapshot = cpu.snapshot();
+
+        // back across the second input load and forward again
+        cpu.goto_cycle(mid).unwrap();
+        assert_eq!(mid_snapshot.memory, cpu.snapshot().memory);
+        assert_eq!(mid_snapshot.d, cpu.snapshot().d);
+        cpu.goto_cycle(end).unwrap();
+        assert_eq!(end_snapshot, cpu.snapshot());
+
+        cpu.step_back(3).unwrap();
+        assert_eq!(end - 3, cpu.cycles());
+        cpu.step_n(3).unwrap();
+        assert_eq!(end_snapshot, cpu.snapshot());
+        assert!(cpu.console.get_log().ends_with(" 9 ok\n"));
+
+        assert_eq!(Err(Error::NotRecorded(0)), cpu.step_back(end + 1));
+    }
+
+    #[test]
+    fn bounded_and_edits() {
+        let mut cpu = cpu_with_history(History::bounded(100, 5));
+        cpu.run(b"1 2 3\n".to_vec()).unwrap();
+        let history = cpu.history.as_ref().unwrap();
+        assert_eq!(5, history.checkpoints().count());
+        let start = history.start().unwrap();
+        assert!(cpu.goto_cycle(start - 1).is_err());
+
+        // a direct edit becomes part of the history
+        let now = cpu.cycles();
+        cpu.write_memory(0x1800, 0xBEEF);
+        cpu.step_n(200).unwrap();
+        cpu.goto_cycle(now + 1).unwrap();
+        assert_eq!(0xBEEF, cpu.memory()[0x1800]);
+
+        let mut cpu = CPU::new();
+        assert_eq!(Err(Error::NotRecorded(0)), cpu.goto_cycle(0));
+    }
+
+    #[test]
+    fn reverse_to_write() {
+        let mut cpu = cpu_with_history(History::new(300));
+        cpu.run(b"variable v 1111 v !\n".to_vec()).unwrap();
+        let first = cpu.cycles();
+        cpu.run(b"2222 v ! 3 4 + drop\n".to_vec()).unwrap();
+        let now = cpu.cycles();
+        let v = cpu.memory().iter().position(|x| *x == 0x2222).unwrap() as u16;
+
+        let cycle = cpu.reverse_to_write(v << 1).unwrap().unwrap();
+        assert!(first < cycle && cycle < now);
+        assert_eq!(0x1111, cpu.memory()[v as usize]);
+
+        // the one before is the store of 1111
+        let cycle = cpu.reverse_to_write(v << 1).unwrap().unwrap();
+        assert!(cycle < first);
+        cpu.step().unwrap();
+        assert_eq!(0x1111, cpu.memory()[v as usize]);
+
+        // nothing else wrote the cell
+        let before = cpu.snapshot();
+        assert_eq!(None, cpu.reverse_to_write(0x3FFE).unwrap());
+        assert_eq!(before, cpu.snapshot());
+    }
+
+    #[test]
+    fn replay_with_waveform() {
+        let mut cpu = cpu_with_history(History::new(300));
+        cpu.waveform = Some(Waveform::new());
+        cpu.run(b"variable v 1111 v ! 3 4 +
+".to_vec()).unwrap();
+        let now = cpu.cycles();
+        let v = cpu.memory().iter().position(|x| *x == 0x1111).unwrap() as u16;
+
+        // replays leave the waveform alone, one clock per instruction run
+        cpu.reverse_to_write(v << 1).unwrap().unwrap();
+        cpu.step_back(10).unwrap();
+        cpu.goto_cycle(now).unwrap();
+        let waveform = cpu.waveform.as_ref().unwrap();
+        assert_eq!(now as usize, waveform.signals().len());
+        assert!(waveform.signals().windows(2).all(|w| w[0].cycle + 1 == w[1].cycle));
+    }
+}
@@ -1,12 +1,74 @@
 use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
 
 use crate::instruction::Instruction::*;
 use crate::instruction::OpCode::*;
 
 const EXPAND: [i8; 4] = [0, 1, -2, -1];
-const OPCODE_NAMES: [&'static str; 16] = [
+const OPCODE_NAMES: [&str; 16] = [
     "T", "N", "T+N", "T∧N", "T∨N", "T⊻N", "¬T", "N=T",
     "N<T", "N≫T", "T-1", "R", "[T]", "N≪T", "D", "Nu<T"];
+// opcode names of the j1.4th assembler
+const J1ASM_NAMES: [&str; 16] = [
+    "t", "n", "t+n", "t&n", "t|n", "t^n", "~t", "n==t",
+    "n<t", "n>>t", "t-1", "rt", "[t]", "n<<t", "dsp", "nu<t"];
+// single instruction words of j1.4th, nip before down1 and without copy and
+// up1 of the pick chain
+const FORTH_NAMES: [(u16, &str); 29] = [
+    (0x6000, "noop"), (0x6203, "+"), (0x6503, "xor"), (0x6303, "and"),
+    (0x6403, "or"), (0x6600, "invert"), (0x6703, "="), (0x6803, "<"),
+    (0x6f03, "u<"), (0x6180, "swap"), (0x6081, "dup"), (0x6103, "drop"),
+    (0x6181, "over"), (0x6003, "nip"), (0x6147, ">r"), (0x6b8d, "r>"),
+    (0x6b81, "r@"), (0x6c00, "@"), (0x6e81, "dsp"), (0x6d03, "lshift"),
+    (0x6903, "rshift"), (0x6a00, "1-"), (0x6c81, "dup@"), (0x6044, "dup>r"),
+    (0x6581, "2dupxor"), (0x6781, "2dup="), (0x6023, "!nip"), (0x6020, "2dup!"),
+    (0x700c, "exit"),
+];
+// R→PC r-1 that `exit` in j1.4th adds to the last instruction of a word
+const FUSED_EXIT: u16 = 0x100c;
+
+/// Notation
+///
+/// How `Instruction::show_as` renders an instruction
+///
+/// # Example
+///
+/// ```
+/// use j1::instruction::{decode, Notation};
+///
+/// let test_cases = [
+///     (Notation::Fields, "ALU     T+N R→PC r-1 d-1"),
+///     (Notation::Forth, "+ exit"),
+///     (Notation::J1Asm, "t+n r->pc r-1 d-1 alu"),
+/// ];
+/// for (notation, expected) in test_cases.iter() {
+///     assert_eq!(*expected, decode(0x720F).unwrap().show_as(*notation));
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Notation {
+    // the fields of the instruction, `Instruction::show`
+    #[default]
+    Fields,
+    // ALU instructions as the j1.4th words they compile from, `exit` fused
+    // onto the last one of a word, others as fields
+    Forth,
+    // source of the j1.4th cross assembler, `t+n d-1 alu` and `0010 call`
+    J1Asm,
+}
+
+impl Notation {
+    pub fn from_name(name: &str) -> Option<Notation> {
+        match name {
+            "fields" => Some(Notation::Fields),
+            "forth" => Some(Notation::Forth),
+            "j1asm" => Some(Notation::J1Asm),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Instruction {
@@ -58,14 +120,14 @@ pub enum Instruction {
     ALU(AluAttributes),
 }
 
-pub fn decode(v: u16) -> Result<Instruction, String> {
+pub fn decode(v: u16) -> Result<Instruction, Error> {
     match v {
         v if v & (1 << 15) == 1 << 15 => Ok(Literal(v & !(1 << 15))),
         v if v & (7 << 13) == 0 => Ok(Jump(v & !(7 << 13))),
         v if v & (7 << 13) == 1 << 13 => Ok(Conditional(v & !(7 << 13))),
         v if v & (7 << 13) == 2 << 13 => Ok(Call(v & !(7 << 13))),
         v if v & (7 << 13) == 3 << 13 => Ok(ALU(decode_alu(v))),
-        _ => Err(format!("Invalid Instruction: {:0>4x}", v)),
+        _ => Err(Error::InvalidInstruction(v)),
     }
 }
 
@@ -77,7 +139,7 @@ pub fn decode_alu(v: u16) -> AluAttributes {
         t2r: v & (1 << 6) != 0,
         n2_at_t: v & (1 << 5) != 0,
         r_dir: EXPAND[((v >> 2) & 3) as usize],
-        d_dir: EXPAND[((v >> 0) & 3) as usize],
+        d_dir: EXPAND[(v & 3) as usize],
     }
 }
 
@@ -91,6 +153,45 @@ impl Instruction {
             ALU(alu) => alu.show(),
         }
     }
+
+    /// Instruction word, the inverse of `decode`. Bit 4 of an ALU word is
+    /// ignored by the J1 and not kept by `decode`, it encodes as 0.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use j1::instruction::{decode, Instruction};
+    ///
+    /// // CALL 0ACC, the call to cmove in cold
+    /// assert_eq!(0x4566, Instruction::Call(0x0ACC >> 1).encode());
+    /// for v in 0..=0xFFFF {
+    ///     let ins = decode(v).unwrap();
+    ///     assert_eq!(Ok(ins), decode(ins.encode()));
+    /// }
+    /// assert_eq!(0x6003, decode(0x6013).unwrap().encode());
+    /// ```
+    pub fn encode(&self) -> u16 {
+        match self {
+            Literal(v) => 0x8000 | (v & 0x7fff),
+            Jump(v) => v & 0x1fff,
+            Conditional(v) => 0x2000 | (v & 0x1fff),
+            Call(v) => 0x4000 | (v & 0x1fff),
+            ALU(alu) => alu.encode(),
+        }
+    }
+
+    pub fn show_as(&self, notation: Notation) -> String {
+        match (notation, self) {
+            (Notation::Fields, _) => self.show(),
+            (Notation::Forth, ALU(alu)) => alu.show_forth(),
+            (Notation::Forth, _) => self.show(),
+            (Notation::J1Asm, Literal(v)) => format!("{:04x} literal", v),
+            (Notation::J1Asm, Jump(v)) => format!("{:04x} branch", v << 1),
+            (Notation::J1Asm, Conditional(v)) => format!("{:04x} ?branch", v << 1),
+            (Notation::J1Asm, Call(v)) => format!("{:04x} call", v << 1),
+            (Notation::J1Asm, ALU(alu)) => alu.show_j1asm(),
+        }
+    }
 }
 
 impl fmt::Display for Instruction {
@@ -106,8 +207,9 @@ impl fmt::Display for Instruction {
 }
 
 #[repr(u16)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub enum OpCode {
+    #[default]
     OpT = 0,
     OpN = 1,
     OpTplusN = 2,
@@ -126,10 +228,6 @@ pub enum OpCode {
     OpNuleT = 15,
 }
 
-impl Default for OpCode {
-    fn default() -> Self { OpT }
-}
-
 impl OpCode {
     pub fn from(x: u16) -> Option<OpCode> {
         match x {
@@ -177,6 +275,101 @@ impl AluAttributes {
         if self.d_dir != 0 { s = format!("{} d{:+}", s, self.d_dir) }
         s
     }
+
+    /// The j1.4th word compiled to this instruction, `name exit` when the
+    /// word is the last of a definition, j1.4th assembler for the rest
+    pub fn show_forth(&self) -> String {
+        let v = self.encode();
+        let name = |v: u16| FORTH_NAMES.iter().find(|x| x.0 == v).map(|x| x.1);
+        if let Some(name) = name(v) {
+            return name.to_string();
+        }
+        let base = v & !FUSED_EXIT;
+        match name(base) {
+            Some(name) if v & FUSED_EXIT == FUSED_EXIT && base & 0x004c == 0 => format!("{} exit", name),
+            _ => self.show_j1asm(),
+        }
+    }
+
+    /// Source for the j1.4th cross assembler, with `r->pc` for R→PC and
+    /// `d-2` that j1.4th has no word for
+    pub fn show_j1asm(&self) -> String {
+        let mut xs = vec![J1ASM_NAMES[self.opcode as usize]];
+        if self.t2n { xs.push("t->n") }
+        if self.t2r { xs.push("t->r") }
+        if self.n2_at_t { xs.push("n->[t]") }
+        if self.r2pc { xs.push("r->pc") }
+        let r_dir = format!("r{:+}", self.r_dir);
+        let d_dir = format!("d{:+}", self.d_dir);
+        if self.r_dir != 0 { xs.push(&r_dir) }
+        if self.d_dir != 0 { xs.push(&d_dir) }
+        xs.push("alu");
+        xs.join(" ")
+    }
+
+    /// Instruction word, the inverse of `decode_alu` with bit 4 clear, stack
+    /// directions are the low 2 bits of `r_dir` and `d_dir`
+    pub fn encode(&self) -> u16 {
+        let dir = |x: i8| (x as u16) & 3;
+        0x6000
+            | (self.opcode as u16) << 8
+            | (self.r2pc as u16) << 12
+            | (self.t2n as u16) << 7
+            | (self.t2r as u16) << 6
+            | (self.n2_at_t as u16) << 5
+            | dir(self.r_dir) << 2
+            | dir(self.d_dir)
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = Error;
+
+    /// Parses the `Instruction::show` text, `LIT     0012` or
+    /// `ALU     T R→PC r-1`, with targets as even byte addresses. ALU fields
+    /// may come in any order but only once each.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || Error::InvalidAsm(s.to_string());
+        let mut words = s.split_whitespace();
+        let name = words.next().ok_or_else(err)?;
+        if name == "ALU" {
+            let opcode = words.next().and_then(|x| OPCODE_NAMES.iter().position(|n| *n == x)).ok_or_else(err)?;
+            let mut alu = AluAttributes { opcode: OpCode::from(opcode as u16).unwrap(), ..AluAttributes::default() };
+            let mut seen = Vec::new();
+            for word in words {
+                // r+1 and r-1 set the same field
+                let field = if word.starts_with(['r', 'd']) { &word[..1] } else { word };
+                if seen.contains(&field) {
+                    return Err(err());
+                }
+                seen.push(field);
+                match word {
+                    "R→PC" => alu.r2pc = true,
+                    "T→N" => alu.t2n = true,
+                    "T→R" => alu.t2r = true,
+                    "N→[T]" => alu.n2_at_t = true,
+                    "r+1" | "r-1" | "r-2" => alu.r_dir = word[1..].parse().unwrap(),
+                    "d+1" | "d-1" | "d-2" => alu.d_dir = word[1..].parse().unwrap(),
+                    _ => return Err(err()),
+                }
+            }
+            return Ok(ALU(alu));
+        }
+
+        let v = words.next().and_then(|x| u16::from_str_radix(x, 16).ok()).ok_or_else(err)?;
+        if words.next().is_some() {
+            return Err(err());
+        }
+        match name {
+            "LIT" if v <= 0x7fff => Ok(Literal(v)),
+            "UBRANCH" | "0BRANCH" | "CALL" if v & 1 == 0 && v <= 0x3ffe => match name {
+                "UBRANCH" => Ok(Jump(v >> 1)),
+                "0BRANCH" => Ok(Conditional(v >> 1)),
+                _ => Ok(Call(v >> 1)),
+            },
+            _ => Err(err()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -264,5 +457,64 @@ mod tests {
             // println!("i.show()   : {}", decoded_show);
         }
     }
-}
 
+    #[test]
+    fn instruction_show_as() {
+        // value, forth, j1asm
+        let test_cases = [
+            (0x8012, "LIT     0012", "0012 literal"),
+            (0x0cea, "UBRANCH 19D4", "19d4 branch"),
+            (0x2010, "0BRANCH 0020", "0020 ?branch"),
+            (0x4566, "CALL    0ACC", "0acc call"),
+            (0x6000, "noop", "t alu"),
+            (0x700c, "exit", "t r->pc r-1 alu"),
+            (0x6081, "dup", "t t->n d+1 alu"),
+            (0x708d, "dup exit", "t t->n r->pc r-1 d+1 alu"),
+            (0x710f, "drop exit", "n r->pc r-1 d-1 alu"),
+            (0x6147, ">r", "n t->r r+1 d-1 alu"),
+            (0x6b8d, "r>", "rt t->n r-1 d+1 alu"),
+            (0x6c00, "@", "[t] alu"),
+            (0x7c0c, "@ exit", "[t] r->pc r-1 alu"),
+            (0x6023, "!nip", "t n->[t] d-1 alu"),
+            (0x6f03, "u<", "nu<t d-1 alu"),
+            (0x6e81, "dsp", "dsp t->n d+1 alu"),
+            (0x7b8d, "r@ exit", "rt t->n r->pc r-1 d+1 alu"),
+            // >r moves the return stack, j1.4th compiles a separate exit after it
+            (0x714f, "n t->r r->pc r-1 d-1 alu", "n t->r r->pc r-1 d-1 alu"),
+            (0x6008, "t r-2 alu", "t r-2 alu"),
+            (0x6002, "t d-2 alu", "t d-2 alu"),
+        ];
+        for (bin, forth, j1asm) in test_cases.iter() {
+            let decoded = decode(*bin).unwrap();
+            assert_eq!((*forth, *j1asm), (decoded.show_as(Notation::Forth).as_str(), decoded.show_as(Notation::J1Asm).as_str()), "{:04X}", bin);
+            assert_eq!(decoded.show(), decoded.show_as(Notation::Fields));
+        }
+        assert_eq!(Some(Notation::J1Asm), Notation::from_name("j1asm"));
+        assert_eq!(None, Notation::from_name("swapforth"));
+    }
+
+    #[test]
+    fn instruction_encode_parse() {
+        for v in 0..=0xffff {
+            let decoded = decode(v).unwrap();
+            // bit 4 of an ALU word is not decoded
+            let word = if v & 0xe000 == 0x6000 { v & !0x10 } else { v };
+            assert_eq!(word, decoded.encode());
+            assert_eq!(Ok(decoded), decoded.show().parse::<Instruction>(), "{:04X}", v);
+        }
+
+        let alu = AluAttributes { opcode: OpTplusN, r2pc: true, r_dir: -1, d_dir: -1, ..AluAttributes::default() };
+        assert_eq!(0x720f, alu.encode());
+        // fields after the opcode in any order
+        assert_eq!(Ok(ALU(alu)), "ALU T+N d-1 r-1 R→PC".parse::<Instruction>());
+
+        let test_cases = [
+            "", "NOP", "LIT", "LIT 8000", "LIT 12 34", "CALL 0ACD", "UBRANCH 4000", "0BRANCH xyz", "ALU", "ALU T+N r+2", "ALU t",
+            // a field given twice
+            "ALU T d+1 d+1", "ALU T d+1 d-1", "ALU T r-1 R→PC r-1", "ALU N T→N T→N",
+        ];
+        for s in test_cases.iter() {
+            assert_eq!(Err(Error::InvalidAsm(s.to_string())), s.parse::<Instruction>());
+        }
+    }
+}
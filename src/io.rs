@@ -0,0 +1,267 @@
+use crate::board::Board;
+use crate::console::Console;
+use crate::error::Error;
+
+/// IoDevice
+///
+/// A memory mapped peripheral. `[T]` reads and `N→[T]` writes to an i/o
+/// address (T & 0xC000 != 0) inside a device's range are sent to the device
+/// with the address as an offset from the start of the range. Devices are
+/// `Send` so a CPU can be moved to a server thread.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::atomic::{AtomicU16, Ordering};
+/// use std::sync::Arc;
+/// use j1::cpu::CPU;
+/// use j1::error::Error;
+/// use j1::io::IoDevice;
+/// use j1::j1e_bin;
+///
+/// // a bank of leds, the state is shared with the caller through an Arc
+/// #[derive(Clone)]
+/// struct Leds(Arc<AtomicU16>);
+///
+/// impl IoDevice for Leds {
+///     fn read(&mut self, _offset: u16) -> Result<u16, Error> {
+///         Ok(self.0.load(Ordering::Relaxed))
+///     }
+///     fn write(&mut self, _offset: u16, value: u16) -> Result<(), Error> {
+///         self.0.store(value, Ordering::Relaxed);
+///         Ok(())
+///     }
+///     fn box_clone(&self) -> Box<dyn IoDevice> {
+///         Box::new(self.clone())
+///     }
+/// }
+///
+/// let leds = Arc::new(AtomicU16::new(0));
+/// let mut cpu = CPU::new();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+/// cpu.io.map(0x6000, 0x6000, Box::new(Leds(leds.clone())));
+///
+/// cpu.run(b"a5 6000 ! 6000 @ 1+ .\n".to_vec()).unwrap();
+/// assert_eq!(0xA5, leds.load(Ordering::Relaxed));
+/// assert!(cpu.console.get_log().ends_with(" A6 ok\n"));
+/// ```
+pub trait IoDevice: Send {
+    fn read(&mut self, offset: u16) -> Result<u16, Error>;
+
+    fn write(&mut self, offset: u16, value: u16) -> Result<(), Error>;
+
+    /// Boxed copy of the device, the CPU is cloned by the debugger and snapshots
+    fn box_clone(&self) -> Box<dyn IoDevice>;
+}
+
+impl Clone for Box<dyn IoDevice> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// The eForth console, offset 0 is tx!/rx@ and offset 1 is ?rx
+impl IoDevice for Console {
+    fn read(&mut self, offset: u16) -> Result<u16, Error> {
+        match offset {
+            0 => Ok(self.read_char() as u16),
+            1 => Ok(self.has_input() as u16),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u16) -> Result<(), Error> {
+        if offset == 0 {
+            self.write_char(value as u8);
+        }
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn IoDevice> {
+        Box::new(self.clone())
+    }
+}
+
+/// Any write halts the CPU with `Error::Bye`, reads return 0
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Halt;
+
+impl IoDevice for Halt {
+    fn read(&mut self, _offset: u16) -> Result<u16, Error> {
+        Ok(0)
+    }
+
+    fn write(&mut self, _offset: u16, _value: u16) -> Result<(), Error> {
+        Err(Error::Bye)
+    }
+
+    fn box_clone(&self) -> Box<dyn IoDevice> {
+        Box::new(*self)
+    }
+}
+
+/// What a range of i/o addresses is connected to
+#[derive(Clone)]
+pub enum Port {
+    // the CPU's `console` field
+    Console,
+    Device(Box<dyn IoDevice>),
+}
+
+/// An inclusive range of i/o addresses and its device
+#[derive(Clone)]
+pub struct Mapping {
+    pub start: u16,
+    pub end: u16,
+    pub port: Port,
+}
+
+/// IoBus
+///
+/// Address decoder for the i/o space. Later mappings shadow earlier ones,
+/// unmapped reads return 0 and unmapped writes are ignored. The default bus
+/// is the j1eforth map of `Board::J1cSim`: console at 0x7000-0x7001 and
+/// `Halt` (bye) at 0x7002.
+#[derive(Clone)]
+pub struct IoBus {
+    mappings: Vec<Mapping>,
+}
+
+impl Default for IoBus {
+    fn default() -> Self {
+        Board::J1cSim.io()
+    }
+}
+
+impl IoBus {
+    /// Bus with nothing mapped
+    pub fn empty() -> Self {
+        IoBus { mappings: Vec::new() }
+    }
+
+    /// Connects a device to the addresses `start` through `end`
+    pub fn map(&mut self, start: u16, end: u16, device: Box<dyn IoDevice>) {
+        self.mappings.push(Mapping { start, end, port: Port::Device(device) });
+    }
+
+    /// Connects the CPU's console to the addresses `start` through `end`
+    pub fn map_console(&mut self, start: u16, end: u16) {
+        self.mappings.push(Mapping { start, end, port: Port::Console });
+    }
+
+    /// Removes every mapping that starts at `start`, true if any were removed
+    pub fn unmap(&mut self, start: u16) -> bool {
+        let len = self.mappings.len();
+        self.mappings.retain(|m| m.start != start);
+        self.mappings.len() != len
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    /// Address of the console status register ?rx, offset 1 of the last
+    /// console mapping that has one
+    pub fn console_status(&self) -> Option<u16> {
+        self.mappings.iter().rev()
+            .find(|m| matches!(m.port, Port::Console) && m.end > m.start)
+            .map(|m| m.start + 1)
+    }
+
+    /// True if a device is mapped at `addr`
+    pub fn is_mapped(&self, addr: u16) -> bool {
+        self.mappings.iter().any(|m| m.start <= addr && addr <= m.end)
+    }
+
+    /// Mapping decoding `addr` and the offset of `addr` in it
+    pub fn decode(&mut self, addr: u16) -> Option<(&mut Port, u16)> {
+        self.mappings.iter_mut().rev()
+            .find(|m| m.start <= addr && addr <= m.end)
+            .map(|m| (&mut m.port, addr - m.start))
+    }
+
+    pub fn read(&mut self, console: &mut Console, addr: u16) -> Result<u16, Error> {
+        match self.decode(addr) {
+            Some((port, offset)) => device(port, console).read(offset).map_err(|e| at(e, addr)),
+            None => Ok(0),
+        }
+    }
+
+    pub fn write(&mut self, console: &mut Console, addr: u16, value: u16) -> Result<(), Error> {
+        match self.decode(addr) {
+            Some((port, offset)) => device(port, console).write(offset, value).map_err(|e| at(e, addr)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn device<'a>(port: &'a mut Port, console: &'a mut Console) -> &'a mut dyn IoDevice {
+    match port {
+        Port::Console => console,
+        Port::Device(d) => d.as_mut(),
+    }
+}
+
+// devices report errors without knowing where they are mapped
+fn at(e: Error, addr: u16) -> Error {
+    match e {
+        Error::Io { message, .. } => Error::Io { addr, message },
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io::*;
+
+    #[derive(Clone)]
+    struct Fails;
+
+    impl IoDevice for Fails {
+        fn read(&mut self, offset: u16) -> Result<u16, Error> {
+            Err(Error::Io { addr: offset, message: "no data".to_string() })
+        }
+        fn write(&mut self, _offset: u16, _value: u16) -> Result<(), Error> {
+            Ok(())
+        }
+        fn box_clone(&self) -> Box<dyn IoDevice> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn default_map() {
+        let mut bus = IoBus::default();
+        let mut console = Console::new();
+        console.load(&mut b"a".to_vec());
+
+        let test_cases = [(0x7001, 1), (0x7000, 'a' as u16), (0x7001, 0), (0x7002, 0), (0x7003, 0), (0xC000, 0)];
+        for (addr, expected) in test_cases.iter() {
+            assert_eq!(Ok(*expected), bus.read(&mut console, *addr));
+        }
+        assert_eq!(Some(0x7001), bus.console_status());
+        assert_eq!(None, IoBus::empty().console_status());
+
+        bus.write(&mut console, 0x7000, 'b' as u16).unwrap();
+        assert_eq!("b", console.get_log());
+        assert_eq!(Err(Error::Bye), bus.write(&mut console, 0x7002, 0));
+        assert_eq!(Ok(()), bus.write(&mut console, 0x4000, 0));
+    }
+
+    #[test]
+    fn map_and_unmap() {
+        let mut bus = IoBus::default();
+        let mut console = Console::new();
+
+        // shadows the console status register
+        bus.map(0x7001, 0x7010, Box::new(Fails));
+        assert_eq!(Err(Error::Io { addr: 0x7005, message: "no data".to_string() }), bus.read(&mut console, 0x7005));
+        assert!(bus.read(&mut console, 0x7001).is_err());
+        assert!(bus.read(&mut console, 0x7000).is_ok());
+
+        assert!(bus.unmap(0x7001));
+        assert!(!bus.unmap(0x7001));
+        assert_eq!(Ok(0), bus.read(&mut console, 0x7001));
+        assert_eq!(2, bus.mappings().len());
+    }
+}
@@ -1,6 +1,20 @@
+pub mod block;
+pub mod board;
+pub mod breakpoint;
 pub mod console;
 pub mod cpu;
+pub mod dictionary;
+pub mod disasm;
+pub mod error;
+pub mod gdb;
+pub mod history;
 pub mod instruction;
+pub mod io;
 pub mod j1e_bin;
+pub mod reference;
+pub mod snapshot;
 pub mod stack;
+pub mod trace;
 pub mod utils;
+pub mod vcd;
+pub mod verilog;
@@ -0,0 +1,531 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::cpu::{Access, CPU};
+use crate::error::Error;
+use crate::io::{Halt, IoBus, IoDevice};
+
+// `unsigned short m[0x4000]` in j1.c
+pub const MEMORY_SIZE: usize = 0x4000;
+
+// 2-bit sign extension of the stack pointer deltas
+const SX: [i16; 4] = [0, 1, -2, -1];
+
+/// Reference
+///
+/// Independent port of the simulator in resources/j1eforth/j1.c that follows
+/// the C code line by line: `pc` is 16 bits, shifts use all of T, `@` fetches
+/// before `!` stores, 0x7001 always reads 1 and a store to 0x7002 sets the
+/// return stack pointer to 0. Memory past 0x4000 cells, undefined behaviour
+/// in C, reads 0 and ignores stores. Shift counts of 32 and more, also
+/// undefined, use the low 5 bits like x86.
+///
+/// j1.c starts by executing `call 0`, `from_cpu` instead copies the state of
+/// a `CPU` so the two can run in lockstep.
+///
+/// # Example
+///
+/// ```
+/// use j1::cpu::CPU;
+/// use j1::reference::Reference;
+///
+/// let mut cpu = CPU::new();
+/// // LIT 0001, LIT 0014, N>>T d-1
+/// cpu.load_bytes(&[0x01, 0x80, 0x14, 0x80, 0x03, 0x69]).unwrap();
+/// let mut reference = Reference::from_cpu(&cpu);
+///
+/// // shifting by 20 keeps 4 bits of T in cpu.rs and all of them in j1.c
+/// cpu.step_n(3).unwrap();
+/// (0..3).for_each(|_| { reference.step(); });
+/// assert_eq!((0x0000, 0x0000), (cpu.state().t, reference.t));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reference {
+    pub t: u16,
+    pub d: [u16; 32],
+    pub r: [u16; 32],
+    // counts cells
+    pub pc: u16,
+    pub dsp: u8,
+    pub rsp: u8,
+    pub memory: Vec<u16>,
+    // bytes for getch and bytes written by putch
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+    // getch found no input, getchar would have returned EOF
+    pub starved: bool,
+    // getch read ESC, which exits j1.c
+    pub exited: bool,
+}
+
+/// Memory cell stored and character written by one `Reference::step`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct ReferenceStep {
+    pub stored: Option<usize>,
+    pub putch: Option<u8>,
+}
+
+impl Reference {
+    /// Reference with the memory, registers, stacks and unread console input
+    /// of `cpu`, memory is cut or padded to j1.c's 0x4000 cells
+    pub fn from_cpu(cpu: &CPU) -> Self {
+        let mut memory = cpu.memory().to_vec();
+        memory.resize(MEMORY_SIZE, 0);
+        let (d, r) = (cpu.data_stack(), cpu.return_stack());
+        let position = cpu.console.reader.position() as usize;
+        let input = cpu.console.reader.get_ref().iter().skip(position).copied().collect();
+        Reference {
+            t: cpu.state().t,
+            d: d.data,
+            r: r.data,
+            pc: cpu.state().pc,
+            dsp: d.sp as u8,
+            rsp: r.sp as u8,
+            memory,
+            input,
+            output: Vec::new(),
+            starved: false,
+            exited: false,
+        }
+    }
+
+    fn push(&mut self, v: u16) {
+        self.dsp = 0x1f & self.dsp.wrapping_add(1);
+        self.d[self.dsp as usize] = self.t;
+        self.t = v;
+    }
+
+    fn pop(&mut self) -> u16 {
+        let v = self.t;
+        self.t = self.d[self.dsp as usize];
+        self.dsp = 0x1f & self.dsp.wrapping_sub(1);
+        v
+    }
+
+    fn load(&self, cell: usize) -> u16 {
+        self.memory.get(cell).copied().unwrap_or(0)
+    }
+
+    fn getch(&mut self) -> u16 {
+        match self.input.pop_front() {
+            None => {
+                self.starved = true;
+                0xffff
+            }
+            Some(0x1b) => {
+                self.exited = true;
+                0x1b
+            }
+            Some(127) => 8,
+            Some(c) => c as u16,
+        }
+    }
+
+    /// Executes the instruction at `pc`, one pass of the loop in `execute`
+    pub fn step(&mut self) -> ReferenceStep {
+        let mut step = ReferenceStep::default();
+        let insn = self.load(self.pc as usize);
+        let mut pc = self.pc.wrapping_add(1);
+        if insn & 0x8000 != 0 {
+            self.push(insn & 0x7fff);
+        } else {
+            let target = insn & 0x1fff;
+            match insn >> 13 {
+                0 => pc = target,
+                1 => {
+                    if self.pop() == 0 {
+                        pc = target;
+                    }
+                }
+                2 => {
+                    self.rsp = 31 & self.rsp.wrapping_add(1);
+                    self.r[self.rsp as usize] = pc << 1;
+                    pc = target;
+                }
+                _ => {
+                    if insn & 0x1000 != 0 {
+                        pc = self.r[self.rsp as usize] >> 1;
+                    }
+                    let (t, s) = (self.t, self.d[self.dsp as usize]);
+                    let bool_value = |b: bool| -> u16 { if b { 0xffff } else { 0 } };
+                    let new_t = match (insn >> 8) & 0xf {
+                        0 => t,
+                        1 => s,
+                        2 => t.wrapping_add(s),
+                        3 => t & s,
+                        4 => t | s,
+                        5 => t ^ s,
+                        6 => !t,
+                        7 => bool_value(t == s),
+                        8 => bool_value((s as i16) < (t as i16)),
+                        9 => ((s as u32) >> (t & 31)) as u16,
+                        0xa => t.wrapping_sub(1),
+                        0xb => self.r[self.rsp as usize],
+                        0xc => match t {
+                            0x7001 => 1,
+                            0x7000 => self.getch(),
+                            _ => self.load((t >> 1) as usize),
+                        },
+                        0xd => ((s as u32) << (t & 31)) as u16,
+                        0xe => ((self.rsp as u16) << 8) + self.dsp as u16,
+                        _ => bool_value(s < t),
+                    };
+                    self.dsp = 31 & (self.dsp as i16 + SX[(insn & 3) as usize]) as u8;
+                    self.rsp = 31 & (self.rsp as i16 + SX[((insn >> 2) & 3) as usize]) as u8;
+                    if insn & 0x80 != 0 {
+                        self.d[self.dsp as usize] = t;
+                    }
+                    if insn & 0x40 != 0 {
+                        self.r[self.rsp as usize] = t;
+                    }
+                    if insn & 0x20 != 0 {
+                        match t {
+                            0x7002 => self.rsp = 0,
+                            0x7000 => {
+                                self.output.push(s as u8);
+                                step.putch = Some(s as u8);
+                            }
+                            _ => {
+                                let cell = (t >> 1) as usize;
+                                if cell < self.memory.len() {
+                                    self.memory[cell] = s;
+                                    step.stored = Some(cell);
+                                }
+                            }
+                        }
+                    }
+                    self.t = new_t;
+                }
+            }
+        }
+        self.pc = pc;
+        step
+    }
+
+    pub fn machine(&self) -> Machine {
+        Machine { pc: self.pc, t: self.t, d: self.d, dsp: self.dsp, r: self.r, rsp: self.rsp }
+    }
+}
+
+/// Registers and both complete stacks, as compared by `lockstep`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Machine {
+    pub pc: u16,
+    pub t: u16,
+    pub d: [u16; 32],
+    pub dsp: u8,
+    pub r: [u16; 32],
+    pub rsp: u8,
+}
+
+impl Machine {
+    pub fn from_cpu(cpu: &CPU) -> Self {
+        let (d, r) = (cpu.data_stack(), cpu.return_stack());
+        let s = cpu.state();
+        Machine { pc: s.pc, t: s.t, d: d.data, dsp: d.sp as u8, r: r.data, rsp: r.sp as u8 }
+    }
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = |xs: &[u16]| xs.iter().map(|x| format!("{:04X}", x)).collect::<Vec<_>>().join(" ");
+        writeln!(f, "pc 0x{:04X}  T 0x{:04X}  dsp {}  rsp {}", self.pc << 1, self.t, self.dsp, self.rsp)?;
+        writeln!(f, "d: {}", hex(&self.d))?;
+        write!(f, "r: {}", hex(&self.r))
+    }
+}
+
+/// A documented difference between cpu.rs and j1.c
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Known {
+    // N<<T or N>>T with T > 15, cpu.rs uses the low 4 bits of T like j1.v
+    WideShift,
+    // [T] together with N→[T], cpu.rs stores before it fetches
+    StoreAndFetch,
+    // N→[T] to 0x7002, cpu.rs halts and j1.c sets the return stack pointer to 0
+    Bye,
+    // other accesses to 0x7000-0x7002, which alias memory in j1.c
+    IoRegister,
+    // the program counter past 0x1FFF, 13 bits in cpu.rs like j1.v
+    WidePc,
+}
+
+impl Known {
+    /// Known difference explaining a mismatch after `insn` ran with `t` on
+    /// top of the stack and moved the j1.c program counter to `next_pc`
+    pub fn classify(insn: u16, t: u16, next_pc: u16) -> Option<Known> {
+        let alu = insn & 0xe000 == 0x6000;
+        let opcode = (insn >> 8) & 0xf;
+        let store = alu && insn & 0x20 != 0;
+        let fetch = alu && opcode == 0xc;
+        if alu && (opcode == 9 || opcode == 0xd) && t > 15 {
+            Some(Known::WideShift)
+        } else if store && t == 0x7002 {
+            Some(Known::Bye)
+        } else if (store || fetch) && (0x7000..=0x7002).contains(&t) {
+            Some(Known::IoRegister)
+        } else if store && fetch {
+            Some(Known::StoreAndFetch)
+        } else if next_pc > 0x1fff {
+            Some(Known::WidePc)
+        } else {
+            None
+        }
+    }
+}
+
+/// The first instruction after which cpu.rs and j1.c disagree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    // instructions run in lockstep before this one
+    pub cycle: u64,
+    // cell address and value of the instruction
+    pub pc: u16,
+    pub insn: u16,
+    pub message: String,
+    pub known: Option<Known>,
+    pub cpu: Box<Machine>,
+    pub reference: Box<Machine>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "mismatch after 0x{:04X} at 0x{:04X}, cycle {}: {}", self.insn, self.pc << 1, self.cycle, self.message)?;
+        if let Some(known) = self.known {
+            writeln!(f, "known difference: {:?}", known)?;
+        }
+        writeln!(f, "cpu.rs\n{}", self.cpu)?;
+        write!(f, "j1.c\n{}", self.reference)
+    }
+}
+
+/// Always reads 1, like ?rx in j1.c
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ready;
+
+impl IoDevice for Ready {
+    fn read(&mut self, _offset: u16) -> Result<u16, Error> {
+        Ok(1)
+    }
+
+    fn write(&mut self, _offset: u16, _value: u16) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn IoDevice> {
+        Box::new(*self)
+    }
+}
+
+/// CPU with j1.c's 0x4000 cells and i/o: the console at 0x7000, ?rx always
+/// ready at 0x7001 and bye at 0x7002
+pub fn j1c_cpu() -> CPU {
+    let mut io = IoBus::empty();
+    io.map_console(0x7000, 0x7000);
+    io.map(0x7001, 0x7001, Box::new(Ready));
+    io.map(0x7002, 0x7002, Box::new(Halt));
+    CPU::builder().memory_size(MEMORY_SIZE).io(io).build().unwrap()
+}
+
+/// Lockstep
+///
+/// Runs `cpu` and `reference` one instruction at a time for at most `n`
+/// instructions, comparing the registers, both stacks, stored memory cells
+/// and console output after every instruction. Stops without a mismatch
+/// when j1.c runs out of console input or exits. Returns the instructions
+/// run, or the first mismatch with the state of both.
+///
+/// # Example
+///
+/// ```
+/// use j1::j1e_bin;
+/// use j1::reference::{j1c_cpu, lockstep, Reference};
+///
+/// let mut cpu = j1c_cpu();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+/// cpu.load_input(b"2 3 * .\n".to_vec());
+/// let mut reference = Reference::from_cpu(&cpu);
+///
+/// assert!(lockstep(&mut cpu, &mut reference, 10_000_000).is_ok());
+/// assert!(String::from_utf8_lossy(&reference.output).ends_with(" 6 ok\r\n"));
+/// ```
+pub fn lockstep(cpu: &mut CPU, reference: &mut Reference, n: u64) -> Result<u64, Mismatch> {
+    for cycle in 0..n {
+        let (pc, t) = (reference.pc, reference.t);
+        let insn = reference.load(pc as usize);
+        let result = cpu.step();
+        let step = reference.step();
+        if reference.starved || reference.exited {
+            return Ok(cycle);
+        }
+
+        let message = match result {
+            Err(e) => Some(format!("cpu.rs failed: {}", e)),
+            Ok(cpu_step) => compare(cpu, reference, &cpu_step.write, &step),
+        };
+        if let Some(message) = message {
+            return Err(Mismatch {
+                cycle,
+                pc,
+                insn,
+                message,
+                known: Known::classify(insn, t, reference.pc),
+                cpu: Box::new(Machine::from_cpu(cpu)),
+                reference: Box::new(reference.machine()),
+            });
+        }
+    }
+    Ok(n)
+}
+
+fn compare(cpu: &CPU, reference: &Reference, write: &Option<Access>, step: &ReferenceStep) -> Option<String> {
+    let (a, b) = (Machine::from_cpu(cpu), reference.machine());
+    if a != b {
+        return Some("registers or stacks differ".to_string());
+    }
+    let putch = match write {
+        Some(Access::Io { addr: 0x7000, value }) => Some(*value as u8),
+        _ => None,
+    };
+    if putch != step.putch {
+        return Some(format!("console output {:?}, j1.c {:?}", putch, step.putch));
+    }
+    let mut cells = step.stored.into_iter().collect::<Vec<_>>();
+    if let Some(Access::Memory { addr, .. }) = write {
+        cells.push((addr >> 1) as usize);
+    }
+    cells.into_iter()
+        .find(|c| cpu.memory().get(*c).copied().unwrap_or(0) != reference.load(*c))
+        .map(|c| format!("memory cell 0x{:04X} differs", c << 1))
+}
+
+/// Runs `input` through an eForth `image` on cpu.rs and j1.c in lockstep
+/// until j1.c runs out of input, see `lockstep`
+pub fn session(image: &[u8], input: &[u8], n: u64) -> Result<u64, Mismatch> {
+    let mut cpu = j1c_cpu();
+    cpu.load_bytes(image).map_err(|e| setup_failed(&cpu, e.to_string()))?;
+    cpu.load_input(input.to_vec());
+    let mut reference = Reference::from_cpu(&cpu);
+    lockstep(&mut cpu, &mut reference, n)
+}
+
+fn setup_failed(cpu: &CPU, message: String) -> Mismatch {
+    let machine = Box::new(Machine::from_cpu(cpu));
+    Mismatch { cycle: 0, pc: 0, insn: 0, message, known: None, cpu: machine.clone(), reference: machine }
+}
+
+/// Random instruction streams
+///
+/// Fills memory with random words from `seed` and runs `n` instructions in
+/// lockstep from pc 0. After a known difference cpu.rs is set to the j1.c
+/// state and the run continues. Returns every mismatch, the last one is
+/// unknown if the run stopped early.
+pub fn fuzz(seed: u32, n: u64) -> Vec<Mismatch> {
+    let mut rng = seed.max(1);
+    let mut next = move || {
+        // xorshift32
+        rng ^= rng << 13;
+        rng ^= rng >> 17;
+        rng ^= rng << 5;
+        rng
+    };
+    let mut cpu = j1c_cpu();
+    let image: Vec<u8> = (0..MEMORY_SIZE).flat_map(|_| (next() as u16).to_le_bytes().to_vec()).collect();
+    cpu.load_bytes(&image).unwrap();
+    let mut reference = Reference::from_cpu(&cpu);
+
+    let mut mismatches = Vec::new();
+    let mut done = 0;
+    while done < n {
+        match lockstep(&mut cpu, &mut reference, n - done) {
+            Ok(k) => done += k.max(1),
+            Err(m) => {
+                done += m.cycle + 1;
+                let known = m.known.is_some();
+                mismatches.push(m);
+                if !known {
+                    break;
+                }
+                sync(&mut cpu, &mut reference);
+            }
+        }
+        // j1.c waiting on input that random code will not get
+        if reference.starved || reference.exited {
+            break;
+        }
+    }
+    mismatches
+}
+
+// sets the cpu.rs state to the j1.c state, j1.c continues from the 13 bit
+// program counter
+fn sync(cpu: &mut CPU, reference: &mut Reference) {
+    reference.pc &= 0x1fff;
+    let mut snapshot = cpu.snapshot();
+    snapshot.memory = reference.memory.clone();
+    snapshot.pc = reference.pc;
+    snapshot.st0 = reference.t;
+    snapshot.d.data = reference.d;
+    snapshot.d.sp = reference.dsp as i8;
+    snapshot.r.data = reference.r;
+    snapshot.r.sp = reference.rsp as i8;
+    for stack in [&mut snapshot.d, &mut snapshot.r].iter_mut() {
+        stack.level = stack.sp as i64;
+        stack.reset_water_marks();
+    }
+    cpu.restore(&snapshot).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::j1e_bin;
+    use crate::reference::*;
+
+    #[test]
+    fn known_differences() {
+        let test_cases: [(&[u8], Known); 4] = [
+            // LIT 0001, LIT 0014, N<<T d-1
+            (&[0x01, 0x80, 0x14, 0x80, 0x03, 0x6D], Known::WideShift),
+            // LIT 0005, LIT 0100, [T] N→[T]
+            (&[0x05, 0x80, 0x00, 0x81, 0x20, 0x6C], Known::StoreAndFetch),
+            // LIT 0000, LIT 7002, N→[T] d-1
+            (&[0x00, 0x80, 0x02, 0xF0, 0x23, 0x60], Known::Bye),
+            // LIT 4000, N T→R d-1 r+1, exit
+            (&[0x00, 0xC0, 0x47, 0x61, 0x0C, 0x70], Known::WidePc),
+        ];
+        for (program, known) in test_cases.iter() {
+            let mut cpu = j1c_cpu();
+            cpu.load_bytes(program).unwrap();
+            let mut reference = Reference::from_cpu(&cpu);
+            let m = lockstep(&mut cpu, &mut reference, 10).unwrap_err();
+            assert_eq!(Some(*known), m.known);
+            assert_eq!((program.len() / 2 - 1) as u16, m.pc);
+            assert!(m.to_string().contains("cpu.rs\npc 0x"));
+        }
+    }
+
+    #[test]
+    fn eforth_sessions() {
+        let test_cases: [&[u8]; 3] = [
+            b"2 3 + .\n",
+            b": sq dup * ; 7 sq . 1 2 3 .s\n",
+            b"decimal\n: fib dup 2 < if drop 1 else dup 2 - recurse swap 1 - recurse + then ;\n10 fib .\nwords\n",
+        ];
+        for input in test_cases.iter() {
+            let n = session(&j1e_bin::J1E_BIN, input, 50_000_000).unwrap();
+            assert!(n > 0 && n < 50_000_000);
+        }
+    }
+
+    #[test]
+    fn random_streams() {
+        let mut known = 0;
+        for seed in 1..=20 {
+            for m in fuzz(seed, 5_000).iter() {
+                assert!(m.known.is_some(), "seed {}\n{}", seed, m);
+                known += 1;
+            }
+        }
+        assert!(known > 0);
+    }
+}
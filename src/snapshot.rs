@@ -0,0 +1,270 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::console::Console;
+use crate::cpu::MAX_MEMORY_SIZE;
+use crate::stack::Stack;
+
+const MAGIC: &[u8; 4] = b"J1SN";
+// version 1 has no stack levels or water marks, version 2 has them as i16
+const VERSION: u16 = 3;
+
+/// Snapshot
+///
+/// The complete machine state: memory, registers, both stacks including
+/// their unused entries, the console buffers and the cycle counter.
+/// Breakpoints, the tracer, limits, stack policies, the timing model and
+/// mapped i/o devices are configuration and are not saved. The j1.v RAM
+/// output register is reloaded from the cell T addresses on restore.
+///
+/// # Example
+///
+/// ```
+/// use j1::cpu::CPU;
+/// use j1::j1e_bin;
+/// use j1::snapshot::Snapshot;
+///
+/// let mut cpu = CPU::new();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+/// cpu.run(b": sq dup * ;\n".to_vec()).unwrap();
+///
+/// let mut buf = Vec::new();
+/// cpu.snapshot().write(&mut buf).unwrap();
+///
+/// let mut restored = CPU::new();
+/// restored.restore(&Snapshot::read(&mut &buf[..]).unwrap()).unwrap();
+/// assert_eq!(cpu.state(), restored.state());
+///
+/// restored.run(b"3 sq .\n".to_vec()).unwrap();
+/// assert!(restored.console.get_log().ends_with(" 9 ok\n"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub memory: Vec<u16>,
+    pub pc: u16,
+    pub st0: u16,
+    pub d: Stack,
+    pub r: Stack,
+    pub cycles: u64,
+    pub console: ConsoleState,
+}
+
+/// `Console` buffers, the reader position is the next byte to be read
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct ConsoleState {
+    pub ar1: u8,
+    pub input: Vec<u8>,
+    pub position: u64,
+    pub writer: Vec<u8>,
+    pub log: String,
+}
+
+impl ConsoleState {
+    pub fn new(console: &Console) -> Self {
+        ConsoleState {
+            ar1: console.ar1[0],
+            input: console.reader.get_ref().clone(),
+            position: console.reader.position(),
+            writer: console.writer.clone(),
+            log: console.get_log(),
+        }
+    }
+
+    pub fn to_console(&self) -> Console {
+        let mut reader = Cursor::new(self.input.clone());
+        reader.set_position(self.position);
+        Console {
+            ar1: [self.ar1],
+            reader,
+            writer: self.writer.clone(),
+            log: self.log.chars().collect(),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u16::<LittleEndian>(VERSION)?;
+        w.write_u32::<LittleEndian>(self.memory.len() as u32)?;
+        for v in self.memory.iter() {
+            w.write_u16::<LittleEndian>(*v)?;
+        }
+        w.write_u16::<LittleEndian>(self.pc)?;
+        w.write_u16::<LittleEndian>(self.st0)?;
+        write_stack(w, &self.d)?;
+        write_stack(w, &self.r)?;
+        w.write_u64::<LittleEndian>(self.cycles)?;
+
+        let c = &self.console;
+        w.write_u8(c.ar1)?;
+        write_bytes(w, &c.input)?;
+        w.write_u64::<LittleEndian>(c.position)?;
+        write_bytes(w, &c.writer)?;
+        write_bytes(w, c.log.as_bytes())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("Not a j1 snapshot".to_string()));
+        }
+        let version = r.read_u16::<LittleEndian>()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(invalid(format!("Unsupported j1 snapshot version {}", version)));
+        }
+        let size = r.read_u32::<LittleEndian>()? as usize;
+        if size > MAX_MEMORY_SIZE {
+            return Err(invalid(format!("Snapshot memory size {} is over {}", size, MAX_MEMORY_SIZE)));
+        }
+        let memory = (0..size).map(|_| r.read_u16::<LittleEndian>()).collect::<io::Result<Vec<u16>>>()?;
+        let pc = r.read_u16::<LittleEndian>()?;
+        let st0 = r.read_u16::<LittleEndian>()?;
+        let d = read_stack(r, version)?;
+        let rs = read_stack(r, version)?;
+        let cycles = r.read_u64::<LittleEndian>()?;
+
+        let ar1 = r.read_u8()?;
+        let input = read_bytes(r)?;
+        let position = r.read_u64::<LittleEndian>()?;
+        let writer = read_bytes(r)?;
+        let log = String::from_utf8(read_bytes(r)?).map_err(|e| invalid(e.to_string()))?;
+        let console = ConsoleState { ar1, input, position, writer, log };
+        Ok(Snapshot { memory, pc, st0, d, r: rs, cycles, console })
+    }
+
+    pub fn save(&self, file_name: &str) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(file_name)?);
+        self.write(&mut w)?;
+        w.flush()
+    }
+
+    pub fn load(file_name: &str) -> io::Result<Self> {
+        Snapshot::read(&mut BufReader::new(File::open(file_name)?))
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_stack<W: Write>(w: &mut W, stack: &Stack) -> io::Result<()> {
+    for v in stack.data.iter() {
+        w.write_u16::<LittleEndian>(*v)?;
+    }
+    w.write_i8(stack.sp)?;
+    w.write_i64::<LittleEndian>(stack.level)?;
+    w.write_i64::<LittleEndian>(stack.high_water)?;
+    w.write_i64::<LittleEndian>(stack.low_water)
+}
+
+fn read_stack<R: Read>(r: &mut R, version: u16) -> io::Result<Stack> {
+    let mut stack = Stack::default();
+    for v in stack.data.iter_mut() {
+        *v = r.read_u16::<LittleEndian>()?;
+    }
+    stack.sp = r.read_i8()?;
+    if !(0..stack.data.len() as i8).contains(&stack.sp) {
+        return Err(invalid(format!("Invalid stack pointer {}", stack.sp)));
+    }
+    let read_level = |r: &mut R| match version {
+        2 => r.read_i16::<LittleEndian>().map(|x| x as i64),
+        _ => r.read_i64::<LittleEndian>(),
+    };
+    if version == 1 {
+        stack.level = stack.sp as i64;
+        stack.reset_water_marks();
+    } else {
+        stack.level = read_level(r)?;
+        stack.high_water = read_level(r)?;
+        stack.low_water = read_level(r)?;
+        if (stack.level & 0x1f) as i8 != stack.sp {
+            return Err(invalid(format!("Stack level {} does not match stack pointer {}", stack.level, stack.sp)));
+        }
+    }
+    Ok(stack)
+}
+
+fn write_bytes<W: Write>(w: &mut W, xs: &[u8]) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(xs.len() as u32)?;
+    w.write_all(xs)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    // reads what is there before trusting the length, a bad one fails
+    // without allocating it first
+    let len = r.read_u32::<LittleEndian>()? as usize;
+    let mut xs = Vec::new();
+    r.take(len as u64).read_to_end(&mut xs)?;
+    if xs.len() != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("Expected {} bytes, read {}", len, xs.len())));
+    }
+    Ok(xs)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::CPU;
+    use crate::j1e_bin;
+    use crate::snapshot::*;
+
+    #[test]
+    fn round_trip() {
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+        cpu.stop_on_idle = true;
+        cpu.run(b"1 2 3 : cube dup dup * * ;\n".to_vec()).unwrap();
+        // leave unread console input
+        cpu.console.load(&mut b"4 cube .\n".to_vec());
+        cpu.step_n(100).unwrap();
+
+        let snapshot = cpu.snapshot();
+        let mut buf = Vec::new();
+        snapshot.write(&mut buf).unwrap();
+        let read = Snapshot::read(&mut &buf[..]).unwrap();
+        assert_eq!(snapshot, read);
+
+        let mut restored = CPU::new();
+        restored.stop_on_idle = true;
+        restored.restore(&read).unwrap();
+        assert_eq!(snapshot, restored.snapshot());
+        assert_eq!(cpu.cycles(), restored.cycles());
+
+        // both machines continue identically
+        cpu.resume().unwrap();
+        restored.resume().unwrap();
+        assert_eq!(cpu.snapshot(), restored.snapshot());
+        assert!(restored.console.get_log().ends_with(" 40 ok\n"));
+    }
+
+    #[test]
+    fn invalid_snapshots() {
+        let mut buf = Vec::new();
+        CPU::new().snapshot().write(&mut buf).unwrap();
+
+        let test_cases: [(usize, u8); 3] = [
+            (0, b'X'),  // magic
+            (4, 9),     // version
+            (6, 1),     // memory size
+        ];
+        for (i, v) in test_cases.iter() {
+            let mut xs = buf.clone();
+            xs[*i] = *v;
+            let result = Snapshot::read(&mut &xs[..]).and_then(|s| CPU::new().restore(&s));
+            assert!(result.is_err());
+        }
+        assert!(Snapshot::read(&mut &buf[..buf.len() - 1]).is_err());
+
+        // lengths far past the end of the data
+        let n = buf.len();
+        let test_cases = [(6, "Snapshot memory size 4294967295 is over 32768"), (n - 4, "Expected 4294967295 bytes, read 0")];
+        for (i, expected) in test_cases.iter() {
+            let mut xs = buf.clone();
+            xs[*i..*i + 4].copy_from_slice(&[0xff; 4]);
+            assert_eq!(*expected, Snapshot::read(&mut &xs[..]).unwrap_err().to_string());
+        }
+    }
+}
@@ -1,26 +1,139 @@
+/// Which of the two CPU stacks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackKind {
+    Data,
+    Return,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackFaultKind {
+    Overflow,
+    Underflow,
+}
+
+/// What the CPU does when a stack overflows or underflows
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum StackPolicy {
+    // wrap modulo 32 like the hardware
+    #[default]
+    Wrap,
+    // wrap and record a `StackEvent` in `CPU::stack_events`
+    Warn,
+    // fail with `Error::StackFault`
+    Trap,
+}
+
+/// An overflow or underflow recorded with `StackPolicy::Warn`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackEvent {
+    pub cycle: u64,
+    // cell address of the faulting instruction
+    pub pc: u16,
+    pub stack: StackKind,
+    pub kind: StackFaultKind,
+}
+
+// deepest level before sp wraps back to 0
+const MAX_LEVEL: i64 = 31;
+
+/// Stack
+///
+/// 32 entry circular stack, `sp` wraps modulo 32 like the hardware. `level`
+/// counts pushes minus pops without wrapping, so an underflow below 0 can be
+/// told apart from an overflow past 31 entries, and the high and low water
+/// marks are the deepest and shallowest levels reached. The CPU only tracks
+/// them while a stack policy other than `Wrap` or `CPU::water_marks` asks
+/// for them.
+///
+/// # Example
+///
+/// ```
+/// use j1::stack::{Stack, StackFaultKind};
+///
+/// let mut s = Stack::default();
+/// s.pop();
+/// assert_eq!(31, s.depth());
+/// assert_eq!(-1, s.level);
+/// assert_eq!(Some(StackFaultKind::Underflow), s.fault_since(0));
+///
+/// s.push(1);
+/// assert_eq!(None, s.fault_since(-1));
+/// assert_eq!((0, -1), (s.high_water, s.low_water));
+/// ```
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub struct Stack {
     pub data: [u16; 32],
     pub sp: i8,
+    pub level: i64,
+    pub high_water: i64,
+    pub low_water: i64,
 }
 
 #[allow(dead_code)]
 impl Stack {
     pub fn move_sp(&mut self, dir: i8) {
-        self.sp = (self.sp + dir) & 0x1f
+        self.move_sp_untracked(dir);
+        self.track(dir)
     }
 
     pub fn push(&mut self, v: u16) {
-        self.sp = (self.sp + 1) & 0x1f;
-        self.data[self.sp as usize] = v
+        self.push_untracked(v);
+        self.track(1)
     }
 
     pub fn pop(&mut self) -> u16 {
+        self.track(-1);
+        self.pop_untracked()
+    }
+
+    // move_sp, push and pop leaving the level and water marks alone, the CPU
+    // tracks an instruction's moves at once
+    pub(crate) fn move_sp_untracked(&mut self, dir: i8) {
+        self.sp = (self.sp + dir) & 0x1f;
+    }
+
+    pub(crate) fn push_untracked(&mut self, v: u16) {
+        self.sp = (self.sp + 1) & 0x1f;
+        self.data[self.sp as usize] = v;
+    }
+
+    pub(crate) fn pop_untracked(&mut self) -> u16 {
         let sp = self.sp;
         self.sp = (self.sp - 1) & 0x1f;
         self.data[sp as usize]
     }
 
+    pub(crate) fn track(&mut self, dir: i8) {
+        self.level += dir as i64;
+        self.high_water = self.high_water.max(self.level);
+        self.low_water = self.low_water.min(self.level);
+    }
+
+    /// Restarts the level from the stack pointer, for levels that were not
+    /// tracked
+    pub fn sync_level(&mut self) {
+        self.level = self.sp as i64;
+        self.reset_water_marks();
+    }
+
+    /// Overflow or underflow if the level moved from `before` further out of
+    /// the 0 to 31 entries that fit without wrapping
+    pub fn fault_since(&self, before: i64) -> Option<StackFaultKind> {
+        if self.level > MAX_LEVEL && self.level > before {
+            Some(StackFaultKind::Overflow)
+        } else if self.level < 0 && self.level < before {
+            Some(StackFaultKind::Underflow)
+        } else {
+            None
+        }
+    }
+
+    /// Restarts the water marks from the current level
+    pub fn reset_water_marks(&mut self) {
+        self.high_water = self.level;
+        self.low_water = self.level;
+    }
+
     pub fn peek(&self) -> u16 {
         self.data[self.sp as usize]
     }
@@ -91,7 +204,36 @@ mod tests {
             32, 33, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23,
             24, 25, 26, 27, 28, 29, 30, 31,
         ];
-        assert_eq!(s.data, data)
+        assert_eq!(s.data, data);
+        assert_eq!((33, 33, 0), (s.level, s.high_water, s.low_water));
+    }
+
+    #[test]
+    fn stack_faults() {
+        let mut s = Stack::default();
+        for _ in 0..31 {
+            s.push(0);
+        }
+        assert_eq!(None, s.fault_since(30));
+
+        // the 32nd entry wraps sp to 0
+        s.push(0);
+        assert_eq!(0, s.depth());
+        assert_eq!(Some(StackFaultKind::Overflow), s.fault_since(31));
+        s.pop();
+        assert_eq!(None, s.fault_since(32));
+
+        let mut s = Stack::default();
+        let test_cases = [(-1, Some(StackFaultKind::Underflow)), (0, None), (1, None), (0, None), (-1, Some(StackFaultKind::Underflow))];
+        for (dir, expected) in test_cases.iter() {
+            let before = s.level;
+            s.move_sp(*dir);
+            assert_eq!(*expected, s.fault_since(before));
+        }
+        assert_eq!((-1, 0, -1), (s.level, s.high_water, s.low_water));
+        s.move_sp(1);
+        s.reset_water_marks();
+        assert_eq!((0, 0), (s.high_water, s.low_water));
     }
 
     #[test]
@@ -0,0 +1,384 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::cpu::CpuState;
+use crate::instruction::decode;
+
+const MAGIC: &[u8; 4] = b"J1TR";
+const VERSION: u16 = 1;
+
+/// One executed instruction, registers and stacks after execution
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    // cell address of the executed instruction
+    pub pc: u16,
+    pub insn: u16,
+    pub t: u16,
+    pub data_stack: Vec<u16>,
+    pub return_stack: Vec<u16>,
+}
+
+impl TraceRecord {
+    /// `Instruction::show()` text of the raw instruction
+    pub fn show(&self) -> String {
+        decode(self.insn).map(|ins| ins.show()).unwrap_or_default()
+    }
+
+    pub fn to_text(&self) -> String {
+        format!("{:>10}: 0x{:04X},0x{:04X},{:<40} T={:04X} d=[{}] r=[{}]",
+                self.cycle, self.pc << 1, self.insn, self.show(), self.t,
+                hex_list(&self.data_stack, " "), hex_list(&self.return_stack, " "))
+    }
+
+    pub fn to_json(&self) -> String {
+        let list = |xs: &[u16]| xs.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(",");
+        format!("{{\"cycle\":{},\"pc\":{},\"addr\":{},\"insn\":{},\"text\":\"{}\",\"t\":{},\"d\":[{}],\"r\":[{}]}}",
+                self.cycle, self.pc, self.pc << 1, self.insn, json_escape(&self.show()), self.t,
+                list(&self.data_stack), list(&self.return_stack))
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W, format: TraceFormat) -> io::Result<()> {
+        match format {
+            TraceFormat::Text => writeln!(w, "{}", self.to_text()),
+            TraceFormat::JsonLines => writeln!(w, "{}", self.to_json()),
+            TraceFormat::Binary => self.write_binary(w),
+        }
+    }
+
+    pub fn write_binary<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u64::<LittleEndian>(self.cycle)?;
+        w.write_u16::<LittleEndian>(self.pc)?;
+        w.write_u16::<LittleEndian>(self.insn)?;
+        w.write_u16::<LittleEndian>(self.t)?;
+        w.write_u8(self.data_stack.len() as u8)?;
+        w.write_u8(self.return_stack.len() as u8)?;
+        for v in self.data_stack.iter().chain(self.return_stack.iter()) {
+            w.write_u16::<LittleEndian>(*v)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_binary<R: Read>(r: &mut R) -> io::Result<Self> {
+        let cycle = r.read_u64::<LittleEndian>()?;
+        let pc = r.read_u16::<LittleEndian>()?;
+        let insn = r.read_u16::<LittleEndian>()?;
+        let t = r.read_u16::<LittleEndian>()?;
+        let d_len = r.read_u8()? as usize;
+        let r_len = r.read_u8()? as usize;
+        let mut read_stack = |len: usize| -> io::Result<Vec<u16>> {
+            (0..len).map(|_| r.read_u16::<LittleEndian>()).collect()
+        };
+        let data_stack = read_stack(d_len)?;
+        let return_stack = read_stack(r_len)?;
+        Ok(TraceRecord { cycle, pc, insn, t, data_stack, return_stack })
+    }
+}
+
+/// Trace output format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    // one human readable line per instruction
+    Text,
+    // one JSON object per line
+    JsonLines,
+    // `J1TR` magic, u16 version, then little endian records
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "json" | "jsonl" => Some(TraceFormat::JsonLines),
+            "bin" | "binary" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// Tracer
+///
+/// Records every instruction executed by the CPU, like the `#if DEBUG`
+/// block of `resources/j1eforth/j1.c`. A streaming tracer writes each record
+/// as it is made, the others keep them in memory until `write`.
+///
+/// # Example
+///
+/// ```
+/// use j1::cpu::CPU;
+/// use j1::j1e_bin;
+/// use j1::trace::{TraceFormat, Tracer};
+///
+/// let mut cpu = CPU::new();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+///
+/// // keep the last 100 instructions
+/// cpu.tracer = Some(Tracer::ring(100));
+/// cpu.run(b"2 3 * .\n".to_vec()).unwrap();
+///
+/// let tracer = cpu.tracer.take().unwrap();
+/// assert_eq!(100, tracer.records().count());
+///
+/// let mut out = Vec::new();
+/// tracer.write(&mut out, TraceFormat::JsonLines).unwrap();
+/// assert_eq!(100, out.iter().filter(|b| **b == b'\n').count());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Tracer {
+    records: VecDeque<TraceRecord>,
+    // ring buffer size, None keeps everything
+    capacity: Option<usize>,
+    stream: Option<Stream>,
+}
+
+// output of a streaming tracer, shared by the clones of a CPU
+#[derive(Clone)]
+struct Stream {
+    sink: Arc<Mutex<Sink>>,
+    format: TraceFormat,
+}
+
+struct Sink {
+    w: Box<dyn Write + Send>,
+    // first write error, records after it are dropped
+    error: Option<io::Error>,
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Stream").field("format", &self.format).finish()
+    }
+}
+
+impl Tracer {
+    /// Tracer that keeps every record
+    pub fn new() -> Self {
+        Tracer::default()
+    }
+
+    /// Tracer that keeps only the last `n` records
+    pub fn ring(n: usize) -> Self {
+        Tracer { records: VecDeque::with_capacity(n), capacity: Some(n), stream: None }
+    }
+
+    /// Tracer that writes every record to `w` in `format` and keeps none,
+    /// `flush` reports write errors
+    pub fn stream<W: Write + Send + 'static>(mut w: W, format: TraceFormat) -> io::Result<Self> {
+        if format == TraceFormat::Binary {
+            write_header(&mut w)?;
+        }
+        let sink = Arc::new(Mutex::new(Sink { w: Box::new(w), error: None }));
+        Ok(Tracer { records: VecDeque::new(), capacity: None, stream: Some(Stream { sink, format }) })
+    }
+
+    pub fn is_stream(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Records the instruction `insn` executed at cell address `pc`, with the
+    /// registers and stacks after it or at the fault it raised
+    pub fn record(&mut self, cycle: u64, pc: u16, insn: u16, state: &CpuState) {
+        if self.capacity == Some(0) {
+            return;
+        }
+        let record = TraceRecord {
+            cycle,
+            pc,
+            insn,
+            t: state.t,
+            data_stack: state.data_stack.clone(),
+            return_stack: state.return_stack.clone(),
+        };
+        if let Some(stream) = &self.stream {
+            let mut sink = stream.sink.lock().unwrap();
+            if sink.error.is_none() {
+                if let Err(e) = record.write(&mut sink.w, stream.format) {
+                    sink.error = Some(e);
+                }
+            }
+            return;
+        }
+        if Some(self.records.len()) == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Flushes a streaming tracer, returning the first write error
+    pub fn flush(&self) -> io::Result<()> {
+        match &self.stream {
+            Some(stream) => {
+                let mut sink = stream.sink.lock().unwrap();
+                match sink.error.take() {
+                    Some(e) => Err(e),
+                    None => sink.w.flush(),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Records oldest first
+    pub fn records(&self) -> impl Iterator<Item=&TraceRecord> {
+        self.records.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear()
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W, format: TraceFormat) -> io::Result<()> {
+        if format == TraceFormat::Binary {
+            write_header(w)?;
+        }
+        for x in self.records.iter() {
+            x.write(w, format)?;
+        }
+        Ok(())
+    }
+
+    /// Reads records written with `TraceFormat::Binary`
+    pub fn read_binary<R: Read>(r: &mut R) -> io::Result<Vec<TraceRecord>> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC || r.read_u16::<LittleEndian>()? != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a j1 binary trace"));
+        }
+        let mut xs = Vec::new();
+        loop {
+            match TraceRecord::read_binary(r) {
+                Ok(x) => xs.push(x),
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(xs),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn write_header<W: Write>(w: &mut W) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_u16::<LittleEndian>(VERSION)
+}
+
+fn hex_list(xs: &[u16], sep: &str) -> String {
+    xs.iter().map(|x| format!("{:04X}", x)).collect::<Vec<String>>().join(sep)
+}
+
+fn json_escape(s: &str) -> String {
+    s.chars().flat_map(|c| match c {
+        '"' => vec!['\\', '"'],
+        '\\' => vec!['\\', '\\'],
+        c => vec![c],
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::CPU;
+    use crate::trace::*;
+
+    fn traced_cpu(tracer: Tracer) -> CPU {
+        let mut cpu = CPU::new();
+        // LIT 0001, LIT 0002, ALU T+N d-1, CALL 0008
+        cpu.load_bytes(&[0x01, 0x80, 0x02, 0x80, 0x03, 0x62, 0x04, 0x40]).unwrap();
+        cpu.tracer = Some(tracer);
+        cpu.step_n(4).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn record() {
+        let cpu = traced_cpu(Tracer::new());
+        let xs: Vec<&TraceRecord> = cpu.tracer.as_ref().unwrap().records().collect();
+        assert_eq!(4, xs.len());
+        let expected = TraceRecord { cycle: 2, pc: 2, insn: 0x6203, t: 3, data_stack: vec![0], return_stack: vec![] };
+        assert_eq!(expected, *xs[2]);
+        assert_eq!(vec![8], xs[3].return_stack);
+        assert_eq!("ALU     T+N d-1", xs[2].show());
+
+        // LIT 7002, ALU T N→[T] faults with bye and is recorded
+        let mut cpu = CPU::new();
+        cpu.load_bytes(&[0x02, 0xf0, 0x20, 0x60]).unwrap();
+        cpu.tracer = Some(Tracer::new());
+        assert!(cpu.step_n(2).is_err());
+        let xs: Vec<&TraceRecord> = cpu.tracer.as_ref().unwrap().records().collect();
+        assert_eq!((2, 1, 0x6020), (xs.len(), xs[1].pc, xs[1].insn));
+    }
+
+    // a writer the test can read back after the tracer took it
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stream() {
+        for format in [TraceFormat::Text, TraceFormat::JsonLines, TraceFormat::Binary].iter() {
+            let out = Shared::default();
+            let cpu = traced_cpu(Tracer::stream(out.clone(), *format).unwrap());
+            let tracer = cpu.tracer.as_ref().unwrap();
+            assert!(tracer.is_stream());
+            assert_eq!(0, tracer.records().count());
+            tracer.flush().unwrap();
+
+            let mut expected = Vec::new();
+            traced_cpu(Tracer::new()).tracer.unwrap().write(&mut expected, *format).unwrap();
+            assert_eq!(expected, *out.0.lock().unwrap(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn ring() {
+        let cpu = traced_cpu(Tracer::ring(2));
+        let cycles: Vec<u64> = cpu.tracer.as_ref().unwrap().records().map(|x| x.cycle).collect();
+        assert_eq!(vec![2, 3], cycles);
+
+        let cpu = traced_cpu(Tracer::ring(0));
+        assert_eq!(0, cpu.tracer.as_ref().unwrap().records().count());
+    }
+
+    #[test]
+    fn formats() {
+        let cpu = traced_cpu(Tracer::new());
+        let tracer = cpu.tracer.as_ref().unwrap();
+
+        let mut out = Vec::new();
+        tracer.write(&mut out, TraceFormat::Text).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            "         2: 0x0004,0x6203,ALU     T+N d-1                          T=0003 d=[0000] r=[]",
+            text.lines().nth(2).unwrap()
+        );
+
+        let mut out = Vec::new();
+        tracer.write(&mut out, TraceFormat::JsonLines).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert_eq!(
+            r#"{"cycle":3,"pc":3,"addr":6,"insn":16388,"text":"CALL    0008","t":3,"d":[0],"r":[8]}"#,
+            json.lines().nth(3).unwrap()
+        );
+
+        let mut out = Vec::new();
+        tracer.write(&mut out, TraceFormat::Binary).unwrap();
+        let xs = Tracer::read_binary(&mut &out[..]).unwrap();
+        let expected: Vec<TraceRecord> = tracer.records().cloned().collect();
+        assert_eq!(expected, xs);
+        assert!(Tracer::read_binary(&mut &b"J1XX"[..]).is_err());
+
+        assert_eq!(Some(TraceFormat::JsonLines), TraceFormat::from_name("json"));
+        assert_eq!(None, TraceFormat::from_name("xml"));
+    }
+}
@@ -0,0 +1,245 @@
+use std::io::{self, Write};
+
+use crate::verilog::{Bus, RESET_CYCLES};
+
+// sys_clk_i of the Papilio Pro, the 32 MHz clk_in doubled by clock.vhd
+pub const CLOCK_PERIOD_PS: u64 = 15_625;
+
+// name, width and VCD identifier of each dumped j1.v signal
+const SIGNALS: [(&str, usize, &str); 11] = [
+    ("sys_clk_i", 1, "!"),
+    ("sys_rst_i", 1, "\""),
+    ("pc", 13, "#"),
+    ("insn", 16, "$"),
+    ("st0", 16, "%"),
+    ("dsp", 5, "&"),
+    ("rsp", 5, "'"),
+    ("io_addr", 16, "("),
+    ("io_rd", 1, ")"),
+    ("io_wr", 1, "*"),
+    ("io_dout", 16, "+"),
+];
+
+/// j1.v registers and i/o port outputs during one clock, see `CPU::signals`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Signals {
+    // clock since reset, `CPU::cycles` before the instruction executes
+    pub cycle: u64,
+    pub pc: u16,
+    pub insn: u16,
+    pub st0: u16,
+    pub dsp: u8,
+    pub rsp: u8,
+    pub bus: Bus,
+}
+
+impl Signals {
+    // values in `SIGNALS` order after sys_clk_i and sys_rst_i
+    fn values(&self) -> [u16; 9] {
+        let b = &self.bus;
+        [self.pc, self.insn, self.st0, self.dsp as u16, self.rsp as u16,
+            b.io_addr, b.io_rd as u16, b.io_wr as u16, b.io_dout]
+    }
+
+    /// Test vector line: pc insn st0 dsp rsp io_addr io_rd io_wr io_dout in hex
+    pub fn to_vector(&self) -> String {
+        let b = &self.bus;
+        format!("{:04X} {:04X} {:04X} {:02X} {:02X} {:04X} {:X} {:X} {:04X}",
+                self.pc, self.insn, self.st0, self.dsp, self.rsp,
+                b.io_addr, b.io_rd as u8, b.io_wr as u8, b.io_dout)
+    }
+}
+
+/// Waveform output format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaveformFormat {
+    // Value Change Dump for a waveform viewer
+    Vcd,
+    // one line of hex values per clock for a testbench, see `Signals::to_vector`
+    Vectors,
+}
+
+impl WaveformFormat {
+    pub fn from_name(name: &str) -> Option<WaveformFormat> {
+        match name {
+            "vcd" => Some(WaveformFormat::Vcd),
+            "vectors" => Some(WaveformFormat::Vectors),
+            _ => None,
+        }
+    }
+}
+
+/// Waveform
+///
+/// Records the j1.v signals of every clock the CPU runs, with the signal
+/// names of resources/j1eforth/fpga/src/j1.v so a run can be compared with
+/// an HDL simulation of the same image. The VCD holds sys_rst_i high for
+/// the `RESET_CYCLES` of papilio-pro-j1.vhd and changes registers at the
+/// rising edge of a `CLOCK_PERIOD_PS` clock.
+///
+/// # Example
+///
+/// ```
+/// use j1::cpu::CPU;
+/// use j1::j1e_bin;
+/// use j1::vcd::{Waveform, WaveformFormat};
+///
+/// let mut cpu = CPU::new();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+/// cpu.waveform = Some(Waveform::new());
+/// cpu.run(b"2 3 * .\n".to_vec()).unwrap();
+///
+/// let waveform = cpu.waveform.take().unwrap();
+/// assert_eq!(cpu.cycles(), waveform.signals().len() as u64);
+///
+/// let mut out = Vec::new();
+/// waveform.write(&mut out, WaveformFormat::Vcd).unwrap();
+/// let vcd = String::from_utf8(out).unwrap();
+/// assert!(vcd.contains("$var reg 13 # pc [12:0] $end"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Waveform {
+    signals: Vec<Signals>,
+}
+
+impl Waveform {
+    pub fn new() -> Self {
+        Waveform::default()
+    }
+
+    pub fn record(&mut self, signals: Signals) {
+        self.signals.push(signals);
+    }
+
+    /// Recorded clocks, oldest first
+    pub fn signals(&self) -> &[Signals] {
+        &self.signals
+    }
+
+    pub fn clear(&mut self) {
+        self.signals.clear()
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W, format: WaveformFormat) -> io::Result<()> {
+        match format {
+            WaveformFormat::Vcd => self.write_vcd(w),
+            WaveformFormat::Vectors => {
+                for x in self.signals.iter() {
+                    writeln!(w, "{}", x.to_vector())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn write_vcd<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "$version j1 cpu emulator $end")?;
+        writeln!(w, "$timescale 1ps $end")?;
+        writeln!(w, "$scope module j1 $end")?;
+        for (name, width, id) in SIGNALS.iter() {
+            match width {
+                1 => writeln!(w, "$var wire 1 {} {} $end", id, name)?,
+                _ => writeln!(w, "$var reg {} {} {} [{}:0] $end", width, id, name, width - 1)?,
+            }
+        }
+        writeln!(w, "$upscope $end")?;
+        writeln!(w, "$enddefinitions $end")?;
+
+        let half = CLOCK_PERIOD_PS / 2;
+        let time = |cycle: u64| (cycle + RESET_CYCLES) * CLOCK_PERIOD_PS;
+        let first = self.signals.first().map_or(0, |x| x.cycle);
+        writeln!(w, "#0")?;
+        writeln!(w, "$dumpvars")?;
+        writeln!(w, "0{}", SIGNALS[0].2)?;
+        writeln!(w, "{}{}", (first == 0) as u8, SIGNALS[1].2)?;
+        for (_, width, id) in SIGNALS[2..].iter() {
+            writeln!(w, "{}", change(*width, id, None))?;
+        }
+        writeln!(w, "$end")?;
+        if first == 0 {
+            for cycle in 0..RESET_CYCLES {
+                writeln!(w, "#{}\n1{}", cycle * CLOCK_PERIOD_PS, SIGNALS[0].2)?;
+                writeln!(w, "#{}\n0{}", cycle * CLOCK_PERIOD_PS + half, SIGNALS[0].2)?;
+            }
+        }
+
+        let mut previous: Option<[u16; 9]> = None;
+        for x in self.signals.iter() {
+            let values = x.values();
+            writeln!(w, "#{}", time(x.cycle))?;
+            writeln!(w, "1{}", SIGNALS[0].2)?;
+            if previous.is_none() {
+                writeln!(w, "0{}", SIGNALS[1].2)?;
+            }
+            for (i, (_, width, id)) in SIGNALS[2..].iter().enumerate() {
+                if previous.is_none_or(|p| p[i] != values[i]) {
+                    writeln!(w, "{}", change(*width, id, Some(values[i])))?;
+                }
+            }
+            writeln!(w, "#{}", time(x.cycle) + half)?;
+            writeln!(w, "0{}", SIGNALS[0].2)?;
+            previous = Some(values);
+        }
+        Ok(())
+    }
+}
+
+// value change of a signal, None is unknown
+fn change(width: usize, id: &str, value: Option<u16>) -> String {
+    match (width, value) {
+        (1, Some(v)) => format!("{}{}", v, id),
+        (1, None) => format!("x{}", id),
+        (_, Some(v)) => format!("b{:b} {}", v, id),
+        (_, None) => format!("bx {}", id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::CPU;
+    use crate::vcd::*;
+
+    #[test]
+    fn signals() {
+        let mut cpu = CPU::new();
+        // LIT 0005, LIT 0010, N→[T] d-1, CALL 0008
+        cpu.load_bytes(&[0x05, 0x80, 0x10, 0x80, 0x23, 0x60, 0x08, 0x40]).unwrap();
+        cpu.waveform = Some(Waveform::new());
+        cpu.step_n(4).unwrap();
+        let waveform = cpu.waveform.take().unwrap();
+        let store = Signals {
+            cycle: 2,
+            pc: 2,
+            insn: 0x6023,
+            st0: 0x10,
+            dsp: 2,
+            rsp: 0,
+            bus: Bus { io_rd: false, io_wr: true, io_addr: 0x10, io_dout: 5 },
+        };
+        assert_eq!(store, waveform.signals()[2]);
+        assert_eq!(1, waveform.signals()[3].dsp);
+
+        let mut out = Vec::new();
+        waveform.write(&mut out, WaveformFormat::Vectors).unwrap();
+        let vectors = String::from_utf8(out).unwrap();
+        assert_eq!("0002 6023 0010 02 00 0010 0 1 0005", vectors.lines().nth(2).unwrap());
+
+        let mut out = Vec::new();
+        waveform.write(&mut out, WaveformFormat::Vcd).unwrap();
+        let vcd = String::from_utf8(out).unwrap();
+        let test_cases = [
+            "$var wire 1 ) io_rd $end",
+            "$var reg 5 & dsp [4:0] $end",
+            "#0\n$dumpvars\n0!\n1\"\nbx #\n",
+            "$end\n#0\n1!\n#7812\n0!\n#15625\n1!\n",
+            // reset ends at the first instruction's clock, only changes follow
+            "#234375\n1!\n0\"\nb0 #\nb1000000000000101 $\n",
+            "#265625\n1!\nb10 #\nb110000000100011 $\nb10000 %\nb10 &\nb10000 (\n1*\nb101 +\n#273437\n0!\n",
+        ];
+        for expected in test_cases.iter() {
+            assert!(vcd.contains(expected), "{}\n{}", expected, vcd);
+        }
+        assert_eq!(Some(WaveformFormat::Vectors), WaveformFormat::from_name("vectors"));
+        assert_eq!(None, WaveformFormat::from_name("fst"));
+    }
+}
@@ -0,0 +1,97 @@
+// clocks sys_rst_i is held high after configuration, rst_counter in
+// papilio-pro-j1.vhd, the first instruction runs on the clock after
+pub const RESET_CYCLES: u64 = 15;
+
+/// Timing
+///
+/// Which model of the J1 `CPU::step` follows. Both run one instruction per
+/// clock, so `CPU::cycles` counts clocks since reset in either model.
+///
+/// `Verilog` follows resources/j1eforth/fpga/src/j1.v:
+/// - `[T]` of a memory address returns the RAM output latched at the last
+///   clock edge, so a fetch right after a store to the same cell reads the
+///   old value
+/// - byte addresses with bit 15 or 14 set are i/o, an unmapped read is 0
+/// - N→[T] fires io_wr at the old T and writes RAM at the new T, only
+///   below 0x4000
+/// - a call at 0x1FFF pushes 0x4000 instead of wrapping to 0
+///
+/// # Example
+///
+/// ```
+/// use j1::cpu::CPU;
+/// use j1::verilog::Timing;
+///
+/// // LIT 0001, LIT 0100, N→[T] d-1, [T]
+/// let program = [0x01, 0x80, 0x00, 0x81, 0x23, 0x60, 0x00, 0x6C];
+/// let mut fetched = Vec::new();
+/// for timing in [Timing::Functional, Timing::Verilog].iter() {
+///     let mut cpu = CPU::builder().timing(*timing).build().unwrap();
+///     cpu.load_bytes(&program).unwrap();
+///     cpu.step_n(4).unwrap();
+///     fetched.push(cpu.state().t);
+/// }
+///
+/// // the RAM read of the store's clock edge is still the old value
+/// assert_eq!(vec![0x0001, 0x0000], fetched);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Timing {
+    // instructions complete before the next one starts, like j1.c
+    #[default]
+    Functional,
+    // RAM read latency and i/o decoding of the j1.v core
+    Verilog,
+}
+
+/// Bus
+///
+/// The j1.v i/o port outputs during the clock the instruction at the program
+/// counter executes: io_rd for every `[T]` and io_wr for every N→[T], memory
+/// or not, with io_addr T and io_dout N. A board decodes io_addr to decide if
+/// a strobe reaches a device.
+///
+/// # Example
+///
+/// ```
+/// use j1::cpu::CPU;
+/// use j1::j1e_bin;
+/// use j1::verilog::{Bus, Timing};
+///
+/// let mut cpu = CPU::builder().timing(Timing::Verilog).build().unwrap();
+/// cpu.load_bytes(&j1e_bin::J1E_BIN).unwrap();
+/// cpu.load_input(b"2 3 * .\n".to_vec());
+///
+/// // clocks with io_wr to the console until forth waits for input
+/// let mut tx = Vec::new();
+/// while !cpu.is_waiting_for_input() {
+///     let bus = cpu.bus();
+///     cpu.step().unwrap();
+///     if bus.io_wr && bus.io_addr == 0x7000 {
+///         tx.push((cpu.cycles() - 1, bus.io_dout as u8));
+///     }
+/// }
+/// let text: String = tx.iter().map(|(_, c)| *c as char).collect();
+/// assert!(text.contains("2 3 * . 6 ok"));
+/// assert!(tx.windows(2).all(|w| w[0].0 < w[1].0));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Bus {
+    pub io_rd: bool,
+    pub io_wr: bool,
+    pub io_addr: u16,
+    pub io_dout: u16,
+}
+
+impl Bus {
+    /// Outputs for instruction word `insn` with `st0` and `st1` on the stack
+    pub fn new(insn: u16, st0: u16, st1: u16) -> Self {
+        let is_alu = insn & 0xe000 == 0x6000;
+        Bus {
+            io_rd: is_alu && (insn >> 8) & 0xf == 0xc,
+            io_wr: is_alu && insn & 0x20 != 0,
+            io_addr: st0,
+            io_dout: st1,
+        }
+    }
+}